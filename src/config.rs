@@ -0,0 +1,89 @@
+use crate::{
+    consts::EDGE_CONFIG_FILENAME,
+    proxy::{cache_key::CacheKeyConfig, routing::RoutingConfig, tenant::TenantConfig},
+    statics::runtime_dir,
+};
+
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    error::Error,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+/// Structured configuration that cannot reasonably be expressed as environment variables
+///
+/// This is read from the JSON file named by `EDGE_CONFIG_FILE` (default `$EDGE_RUNTIME_DIR/config.json`).
+/// Every section is optional, and the default file need not exist.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EdgeConfig {
+    pub cache_key: CacheKeyConfig,
+    pub tenants: TenantConfig,
+    pub routing: RoutingConfig,
+}
+
+impl EdgeConfig {
+    fn validate(&self) -> Result<(), String> {
+        self.cache_key.validate()?;
+        self.tenants.validate()?;
+        self.routing.validate()
+    }
+}
+
+static EDGE_CONFIG: OnceLock<EdgeConfig> = OnceLock::new();
+pub fn edge_config() -> &'static EdgeConfig {
+    EDGE_CONFIG.get_or_init(EdgeConfig::default)
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// Must be called once at startup before the proxy handles any requests.
+// A config file that cannot be read or parsed is a fatal error, rather than silently running with the defaults
+pub fn load_edge_config() -> Result<(), Box<dyn Error>> {
+    let explicit_path = std::env::var("EDGE_CONFIG_FILE").ok().map(PathBuf::from);
+    let path = explicit_path
+        .clone()
+        .unwrap_or_else(|| Path::new(runtime_dir()).join(EDGE_CONFIG_FILENAME));
+
+    let config = match std::fs::read(&path) {
+        Ok(bytes) => {
+            tracing::info!("Loading configuration from {}", path.display());
+            serde_json::from_slice::<EdgeConfig>(&bytes)
+                .map_err(|e| e.to_string())
+                .and_then(|config| config.validate().map(|_| config))
+                .map_err(|e| format!("Invalid configuration file {}: {e}", path.display()))?
+        },
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound && explicit_path.is_none() => {
+            tracing::debug!("No configuration file found at {}, using defaults", path.display());
+            EdgeConfig::default()
+        },
+        Err(e) => return Err(format!("Unable to read configuration file {}: {e}", path.display()).into()),
+    };
+
+    EDGE_CONFIG
+        .set(config)
+        .map_err(|_| "Configuration has already been loaded".into())
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+/// Look up a lowercase host name in a table keyed by host name or by `*.domain` wildcard
+///
+/// An exact match always wins, then the most specific wildcard: `a.b.example.com` tries `*.b.example.com`, then
+/// `*.example.com`, then `*.com`
+pub fn lookup_host<'a, T>(table: &'a HashMap<String, T>, host: &str) -> Option<&'a T> {
+    if let Some(value) = table.get(host) {
+        return Some(value);
+    }
+
+    let mut domain = host;
+    while let Some((_, parent)) = domain.split_once('.') {
+        if let Some(value) = table.get(&format!("*.{parent}")) {
+            return Some(value);
+        }
+        domain = parent;
+    }
+
+    None
+}
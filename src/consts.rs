@@ -2,16 +2,38 @@ use std::time::Duration;
 
 pub const DEFAULT_PROXY_PORT_HTTP: u16 = 6188;
 pub const DEFAULT_PROXY_PORT_HTTPS: u16 = 6143;
+pub const DEFAULT_INSPECTOR_LISTEN_ADDR: &str = "127.0.0.1:8080";
 pub const DEFAULT_PORT_HTTP: u16 = 80;
 pub const DEFAULT_PORT_HTTPS: u16 = 443;
 
 pub const DEFAULT_CACHE_SIZE_BYTES: usize = 2 * 1024 * 1024 * 1024; // Default cache size = 2Gb
 pub const DEFAULT_READ_BUFFER_SIZE: usize = 256 * 1024; // This will probably need to be made configurable
 
-pub const DEFAULT_RUNTIME_DIR: &'static str = "/tmp/edge-cdn-store";
-pub const CACHE_STATE_FILENAME: &'static str = "_cache_state.json";
+pub const DEFAULT_RUNTIME_DIR: &str = "/tmp/edge-cdn-store";
+pub const CACHE_STATE_FILENAME: &str = "_cache_state.json";
+pub const EDGE_CONFIG_FILENAME: &str = "config.json";
+pub const TAG_INDEX_FILENAME: &str = "_tag_index.json";
+pub const DEFAULT_TAG_INDEX_SAVE_INTERVAL: Duration = Duration::from_secs(60);
+pub const BAN_LIST_FILENAME: &str = "_bans.json";
+pub const DEFAULT_BAN_LIST_LIMIT: usize = 1000;
 
 pub const ONE_HOUR: Duration = Duration::from_secs(3600);
+pub const DEFAULT_STATUS_TTLS: &str = "301=86400,308=86400,404=30,410=30,4xx=30,5xx=10";
+pub const DEFAULT_STALE_WHILE_REVALIDATE_SECS: u32 = 0;
+pub const DEFAULT_STALE_IF_ERROR_SECS: u32 = 0;
+pub const DEFAULT_CACHE_AUTHENTICATED_CONTENT: bool = false;
+pub const DEFAULT_IGNORE_CLIENT_DIRECTIVES: bool = false;
+pub const DEFAULT_CACHE_LOCK_AGE_TIMEOUT: Duration = Duration::from_secs(10);
+pub const DEFAULT_CACHE_LOCK_WAIT_TIMEOUT: Duration = Duration::from_secs(15);
+pub const DEFAULT_CACHE_ADMISSION_THRESHOLD: u8 = 1;
+pub const DEFAULT_CACHE_ADMISSION_WINDOW: Duration = ONE_HOUR;
+pub const DEFAULT_TRUSTED_PROXY_CIDRS: &str = "";
+pub const DEFAULT_EGRESS_ALLOW_CIDRS: &str = "";
+pub const DEFAULT_FORWARDED_HEADERS_MODE: &str = "append";
+pub const DEFAULT_PURGE_ALLOW_CIDRS: &str = "";
+pub const PURGE_SECRET_HEADER: &str = "x-purge-secret";
+pub const PURGE_MODE_HEADER: &str = "x-purge-mode";
+pub const CACHE_STATUS_NAME: &str = "edge-cdn-store";
 pub const HTTPS: &str = "https";
 pub const HTTP: &str = "http";
 pub const HEX_CHARS: &[u8] = b"0123456789ABCDEF";
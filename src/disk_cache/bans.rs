@@ -0,0 +1,244 @@
+use crate::{
+    consts::{BAN_LIST_FILENAME, DEFAULT_BAN_LIST_LIMIT},
+    disk_cache::write_atomic,
+    utils::{env_var_or_num, parse_host_authority},
+};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::{
+    path::{Path, PathBuf},
+    sync::RwLock,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+/// A ban as submitted to the inspector
+///
+/// ```json
+/// { "host": "www.example.com", "path": "^/images/.*\\.png$", "before": 1760000000 }
+/// ```
+///
+/// Every field is optional. `before` is in seconds since the Unix epoch and defaults to the time the ban is added
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BanSpec {
+    pub host: Option<String>,
+    pub path: Option<String>,
+    pub before: Option<f64>,
+}
+
+/// Invalidates every cached object stored before `before` whose host and path match
+///
+/// `host` must equal the requested host name, ignoring any port, and `path` is an unanchored regex matched against
+/// the normalised path and query string.
+/// A condition that is not given matches every object, so a ban with neither invalidates the whole cache
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Ban {
+    pub id: u64,
+    pub host: Option<String>,
+    pub path: Option<String>,
+    /// Seconds since the Unix epoch
+    pub before: f64,
+    #[serde(skip)]
+    path_regex: Option<Regex>,
+}
+
+impl Ban {
+    fn new(id: u64, spec: BanSpec, now: SystemTime) -> Result<Self, String> {
+        let now_secs = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64();
+
+        // A ban only ever applies to objects that are already stored, otherwise it would also reject their replacements
+        let before = match spec.before {
+            Some(before) if !before.is_finite() || before < 0.0 => return Err("before must be a Unix time".into()),
+            Some(before) => before.min(now_secs),
+            None => now_secs,
+        };
+
+        let host = spec
+            .host
+            .map(|host| host.trim().trim_start_matches('[').trim_end_matches(']').to_ascii_lowercase())
+            .filter(|host| !host.is_empty());
+
+        let mut ban = Self {
+            id,
+            host,
+            path: spec.path.filter(|path| !path.is_empty()),
+            before,
+            path_regex: None,
+        };
+        ban.compile()?;
+
+        Ok(ban)
+    }
+
+    fn compile(&mut self) -> Result<(), String> {
+        self.path_regex = match &self.path {
+            Some(pattern) => Some(Regex::new(pattern).map_err(|e| format!("invalid path regex: {e}"))?),
+            None => None,
+        };
+
+        Ok(())
+    }
+
+    fn stored_before(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs_f64(self.before)
+    }
+
+    fn matches(&self, host: &str, path: &str) -> bool {
+        self.host.as_ref().is_none_or(|ban_host| ban_host == host)
+            && self.path_regex.as_ref().is_none_or(|re| re.is_match(path))
+    }
+
+    // Every object this ban invalidates is also invalidated by a later ban that matches at least the same objects
+    fn superseded_by(&self, later: &Ban) -> bool {
+        self.before <= later.before
+            && (later.host.is_none() || later.host == self.host)
+            && (later.path.is_none() || later.path == self.path)
+    }
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+/// The bans currently in force
+///
+/// Bans are evaluated lazily: nothing is removed from disk when a ban is added, but every subsequent hit on an object
+/// stored before the ban is treated as a miss, so the object is replaced the next time it is requested.
+/// The list is written to `$CACHE_ROOT/_bans.json` whenever it changes, and a ban stays in force until it is deleted or
+/// superseded by a later ban that matches at least the same objects.
+/// At most `BAN_LIST_LIMIT` bans are held, after which new bans are refused
+pub struct BanList {
+    path: PathBuf,
+    limit: usize,
+    bans: RwLock<Bans>,
+    // Saves must reach the disk in the same order as the changes they record
+    save_lock: tokio::sync::Mutex<()>,
+}
+
+// Ids are never reused, even after the ban holding the highest id has been removed
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Bans {
+    next_id: u64,
+    bans: Vec<Ban>,
+}
+
+impl BanList {
+    pub fn load(root: &Path) -> Self {
+        let path = root.join(BAN_LIST_FILENAME);
+        let mut bans = Bans::default();
+
+        if let Ok(json) = std::fs::read(&path) {
+            match serde_json::from_slice::<Bans>(&json) {
+                Ok(saved) => {
+                    bans.next_id = saved.next_id;
+
+                    for mut ban in saved.bans {
+                        bans.next_id = bans.next_id.max(ban.id + 1);
+
+                        match ban.compile() {
+                            Ok(()) => bans.bans.push(ban),
+                            Err(e) => tracing::warn!("Ignoring ban {}: {e}", ban.id),
+                        }
+                    }
+                },
+                Err(e) => tracing::warn!("Ignoring unreadable ban list: {e}"),
+            }
+        }
+
+        Self {
+            path,
+            limit: env_var_or_num("BAN_LIST_LIMIT", DEFAULT_BAN_LIST_LIMIT),
+            bans: RwLock::new(bans),
+            save_lock: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    pub fn list(&self) -> Vec<Ban> {
+        self.read().bans.clone()
+    }
+
+    /// Add a ban, which takes effect immediately even if it cannot be saved.
+    /// Any existing ban that the new one supersedes is removed
+    pub async fn add(&self, spec: BanSpec) -> Result<Ban, String> {
+        let ban = {
+            let mut bans = self.write();
+            let ban = Ban::new(bans.next_id.max(1), spec, SystemTime::now())?;
+
+            let in_force = bans.bans.iter().filter(|old| !old.superseded_by(&ban)).count();
+            if in_force >= self.limit {
+                return Err(format!("the ban list already holds the limit of {} bans", self.limit));
+            }
+
+            bans.bans.retain(|old| {
+                let superseded = old.superseded_by(&ban);
+                if superseded {
+                    tracing::info!("Ban {} superseded by ban {}", old.id, ban.id);
+                }
+                !superseded
+            });
+            bans.next_id = ban.id + 1;
+            bans.bans.push(ban.clone());
+            ban
+        };
+
+        tracing::info!("Added ban {ban:?}");
+        self.save().await;
+        Ok(ban)
+    }
+
+    pub async fn remove(&self, id: u64) -> Option<Ban> {
+        let removed = {
+            let mut bans = self.write();
+            let idx = bans.bans.iter().position(|ban| ban.id == id)?;
+            bans.bans.remove(idx)
+        };
+
+        tracing::info!("Removed ban {id}");
+        self.save().await;
+        Some(removed)
+    }
+
+    // - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+    /// The id of a ban that invalidates the object stored at `stored` under the given primary cache key.
+    /// Only bans added after the object was stored are considered
+    pub fn matching(&self, primary_key: &str, stored: SystemTime) -> Option<u64> {
+        let bans = self.read();
+        let mut newer = bans.bans.iter().filter(|ban| stored < ban.stored_before()).peekable();
+        newer.peek()?;
+
+        let (host, path) = host_and_path(primary_key)?;
+        newer.find(|ban| ban.matches(&host, path)).map(|ban| ban.id)
+    }
+
+    async fn save(&self) {
+        let _saving = self.save_lock.lock().await;
+        let json = serde_json::to_vec_pretty(&*self.read());
+
+        match json {
+            Ok(json) => {
+                if let Err(e) = write_atomic(&self.path, &json).await {
+                    tracing::error!("Failed to save the ban list: {e}");
+                }
+            },
+            Err(e) => tracing::error!("Failed to serialize the ban list: {e}"),
+        }
+    }
+
+    // A panic while the lock was held cannot leave the list half-updated, so carry on regardless
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, Bans> {
+        self.bans.read().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, Bans> {
+        self.bans.write().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// A primary cache key has the form "scheme://authority/path?query", where the authority is already lowercase
+fn host_and_path(primary_key: &str) -> Option<(String, &str)> {
+    let rest = primary_key.split_once("://")?.1;
+    let (authority, path) = rest.find('/').map_or((rest, "/"), |idx| rest.split_at(idx));
+    let (host, _port) = parse_host_authority(authority).ok()?;
+
+    Some((host, path))
+}
@@ -11,7 +11,6 @@ use serde::{Deserialize, Serialize};
 use std::{
     io::{BufReader, Result},
     path::PathBuf,
-    sync::Arc,
 };
 
 // - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
@@ -28,7 +27,7 @@ pub struct CacheStatistics {
 // - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
 /// Persist cache data
 pub struct PersistCacheOnShutdown {
-    pub cache: Arc<&'static DiskCache>,
+    pub cache: &'static DiskCache,
 }
 
 impl_trace!(PersistCacheOnShutdown);
@@ -1,8 +1,15 @@
-use crate::{metrics::CacheMetrics, logger::{Trace, impl_trace, trace_fn_exit_with_err}};
+use crate::{
+    disk_cache::tags::TagIndex,
+    metrics::CacheMetrics,
+    logger::{Trace, impl_trace, trace_fn_exit_with_err},
+};
 
 use async_trait::async_trait;
 use bytes::Bytes;
-use pingora_cache::storage::{HandleMiss, MissFinishType};
+use pingora_cache::{
+    storage::{HandleMiss, MissFinishType},
+    CacheKey,
+};
 use pingora_error::{Error, ErrorType};
 use std::{
     path::{Path, PathBuf},
@@ -25,6 +32,11 @@ pub struct DiskMissHandler {
     pub meta_internal: Vec<u8>,
     pub meta_header: Vec<u8>,
 
+    // Recorded in the tag index once the object has been committed
+    pub key: CacheKey,
+    pub tags: Vec<String>,
+    pub tag_index: &'static TagIndex,
+
     pub metrics: Arc<CacheMetrics>,
 }
 
@@ -62,7 +74,7 @@ impl HandleMiss for DiskMissHandler {
         let mut file = match fs::OpenOptions::new().append(true).open(&self.tmp_path).await {
             Ok(f) => f,
             Err(e) => {
-                return trace_fn_exit_with_err(fn_name, &format!("Can't open tmp file for append: {e}"), None,true);
+                return trace_fn_exit_with_err(fn_name, &format!("Can't open tmp file for append: {e}"), None, true);
             },
         };
 
@@ -103,12 +115,12 @@ impl HandleMiss for DiskMissHandler {
             let mut cache_file = match fs::File::create(&self.body_path).await {
                 Ok(f) => f,
                 Err(e) => {
-                    return trace_fn_exit_with_err(fn_name, &format!("Can't create cache file: {e}"), None,false);
+                    return trace_fn_exit_with_err(fn_name, &format!("Can't create cache file: {e}"), None, false);
                 },
             };
 
             if let Err(e) = tokio::io::copy(&mut tmp_file, &mut cache_file).await {
-                return trace_fn_exit_with_err(fn_name, &format!("Failed to copy tmp to cache body: {e}"), None,false);
+                return trace_fn_exit_with_err(fn_name, &format!("Failed to copy tmp to cache body: {e}"), None, false);
             }
 
             // clean up tmp
@@ -124,6 +136,8 @@ impl HandleMiss for DiskMissHandler {
             return trace_fn_exit_with_err(fn_name, &format!("Failed to write cache hdr: {e}"), None, false);
         }
 
+        self.tag_index.record(&self.key, self.tags.clone());
+
         self.metrics.inserts.inc();
         self.metrics.size_bytes.add(self.tmp_bytes_written as i64);
 
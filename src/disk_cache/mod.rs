@@ -1,10 +1,21 @@
+pub(crate) mod bans;
 pub(crate) mod cache_statistics;
 mod handle_hit;
 mod handle_miss;
+pub(crate) mod soft_purge;
+pub(crate) mod tags;
+pub(crate) mod tenants;
 
 use crate::{
     consts::{DEFAULT_CACHE_SIZE_BYTES, DEFAULT_READ_BUFFER_SIZE},
-    disk_cache::{cache_statistics::fetch_cache_state, handle_hit::DiskHitHandler, handle_miss::DiskMissHandler},
+    disk_cache::{
+        bans::BanList,
+        cache_statistics::fetch_cache_state,
+        handle_hit::DiskHitHandler,
+        handle_miss::DiskMissHandler,
+        tags::{response_tags, TagIndex},
+        tenants::dir_hash,
+    },
     logger::{impl_trace, trace_fn_exit_with_err, Trace},
     metrics::CacheMetrics,
     statics::cache_dir,
@@ -13,7 +24,8 @@ use crate::{
 
 use async_trait::async_trait;
 use pingora_cache::{
-    eviction::simple_lru::Manager as LruManager, key::{CacheHashKey, CompactCacheKey},
+    eviction::{simple_lru::Manager as LruManager, EvictionManager},
+    key::{CacheHashKey, CompactCacheKey},
     storage::{HitHandler, MissHandler, PurgeType, Storage},
     trace::SpanHandle,
     CacheKey,
@@ -27,6 +39,11 @@ use std::{
 };
 use tokio::{fs, fs::File, join};
 
+// Secondary variants of a response are stored in this subdirectory of the primary slot
+const VARIANTS_DIR: &str = "variants";
+// Every tenant other than the default has its own subdirectory of the cache root
+const TENANTS_DIR: &str = "tenants";
+
 // - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
 // Define disk cache and eviction policy
 static DISK_CACHE: OnceLock<DiskCache> = OnceLock::new();
@@ -54,17 +71,31 @@ pub fn eviction_manager() -> &'static LruManager {
 // - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
 /// The cache directory structure is as follows, with each part of the cached data living in its own directory
 ///
-/// The value of `hash` is provided by Pingora
+/// The value of `hash` is the primary key hash provided by Pingora
+///
+///   * `$TENANT_ROOT/hash[0..2]/hash[2..4]/hash/body`
+///   * `$TENANT_ROOT/hash[0..2]/hash[2..4]/hash/meta`
+///   * `$TENANT_ROOT/hash[0..2]/hash[2..4]/hash/hdr`
+///
+/// The tenant is taken from the key's user tag, which the proxy sets to the same value as the namespace.
+/// `$TENANT_ROOT` is `$CACHE_ROOT` for the default (unnamed) tenant and `$CACHE_ROOT/tenants/<tenant>` for all others,
+/// so each tenant's objects can be listed, measured and purged together
+///
+/// When a response carries a `Vary` header, the first variant to be stored occupies the primary slot above.
+/// Each further variant lives under the same primary directory, named after its variance hash
+///
+///   * `$TENANT_ROOT/hash[0..2]/hash[2..4]/hash/variants/variance/{body,meta,hdr}`
 ///
-///   * `$CACHE_ROOT/hash[0..2]/hash[2..4]/hash/body`
-///   * `$CACHE_ROOT/hash[0..2]/hash[2..4]/hash/meta`
-///   * `$CACHE_ROOT/hash[0..2]/hash[2..4]/hash/hdr`
+/// Alongside the objects, the cache keeps an index of the tags assigned to them by `Surrogate-Key` and `Cache-Tag`,
+/// and the list of bans that invalidate them
 pub struct DiskCache {
     pub root: PathBuf,
     pub start_time: std::time::SystemTime,
     #[allow(dead_code)]
     pub uptime: AtomicU64,
     pub metrics: Arc<CacheMetrics>,
+    pub tags: TagIndex,
+    pub bans: BanList,
 }
 
 impl_trace!(DiskCache);
@@ -91,28 +122,107 @@ impl DiskCache {
             start_time: std::time::SystemTime::now(),
             uptime: AtomicU64::new(0),
             metrics: Arc::new(CacheMetrics::new(prev_size)),
+            tags: TagIndex::load(root.as_ref()),
+            bans: BanList::load(root.as_ref()),
         }
     }
 
     // - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
     fn path_from_key(&self, key: &CacheKey) -> (String, PathBuf, PathBuf, PathBuf, PathBuf) {
-        self.path_from_hash(key.combined())
+        self.path_from_hash(key.primary(), key.variance(), key.user_tag())
     }
 
     // - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
     fn path_from_compact_key(&self, key: &CompactCacheKey) -> (String, PathBuf, PathBuf, PathBuf, PathBuf) {
-        self.path_from_hash(key.combined())
+        self.path_from_hash(key.primary(), key.variance(), key.user_tag())
     }
 
     // - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
-    fn path_from_hash(&self, hash: String) -> (String, PathBuf, PathBuf, PathBuf, PathBuf) {
-        let dir = self.root.join(&hash[0..2]).join(&hash[2..4]).join(hash.clone());
+    fn path_from_hash(
+        &self,
+        hash: String,
+        variance: Option<String>,
+        tenant: &str,
+    ) -> (String, PathBuf, PathBuf, PathBuf, PathBuf) {
+        let primary_dir = self.tenant_root(tenant).join(&hash[0..2]).join(&hash[2..4]).join(&hash);
+        let (hash, dir) = match variance {
+            Some(variance) => (format!("{hash}-{variance}"), primary_dir.join(VARIANTS_DIR).join(variance)),
+            None => (hash, primary_dir),
+        };
         let body = dir.join("body");
         let meta = dir.join("meta");
         let hdr = dir.join("hdr");
 
         (hash, dir, body, meta, hdr)
     }
+
+    // - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+    // Remove every secondary variant stored beneath a primary slot, returning the number of body bytes released.
+    // Each variant is also removed from the eviction manager, so that it no longer counts towards the cache size
+    async fn purge_variants(&self, key: &CompactCacheKey, primary_dir: &Path) -> u64 {
+        let variants_dir = primary_dir.join(VARIANTS_DIR);
+        let mut entries = match fs::read_dir(&variants_dir).await {
+            Ok(entries) => entries,
+            Err(_) => return 0,
+        };
+        let mut purged_bytes = 0u64;
+
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let dir = entry.path();
+            let body_path = dir.join("body");
+
+            if let Some(variance) = dir_hash(&dir) {
+                eviction_manager().remove(&CompactCacheKey {
+                    primary: key.primary,
+                    variance: Some(Box::new(variance)),
+                    user_tag: key.user_tag.clone(),
+                });
+            }
+
+            if let Ok(md) = fs::metadata(&body_path).await
+                && fs::remove_file(&body_path).await.is_ok()
+            {
+                self.metrics.evictions.inc();
+                purged_bytes += md.len();
+            }
+
+            let _ = fs::remove_file(dir.join("meta")).await;
+            let _ = fs::remove_file(dir.join("hdr")).await;
+            let _ = fs::remove_dir(&dir).await;
+        }
+
+        let _ = fs::remove_dir(&variants_dir).await;
+        purged_bytes
+    }
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// Replace the contents of a file by writing to a temporary sibling, then renaming it over the original
+async fn write_atomic(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let tmp_path = path.with_extension(format!("tmp-{}-{}", std::process::id(), nanos));
+
+    if let Err(e) = fs::write(&tmp_path, contents).await {
+        let _ = fs::remove_file(&tmp_path).await;
+        return Err(e);
+    }
+
+    fs::rename(&tmp_path, path).await
+}
+
+// Replace the metadata of a stored object.
+// A revalidation may happen while other requests are reading this entry, so never expose a partial file
+async fn write_meta(meta: &CacheMeta, meta_path: &Path, hdr_path: &Path) -> Result<(), String> {
+    let (meta_internal, meta_header) = meta.serialize().map_err(|e| format!("failed to serialize meta: {e}"))?;
+
+    tracing::debug!("     updating meta");
+    write_atomic(meta_path, &meta_internal).await.map_err(|e| format!("failed to update meta: {e}"))?;
+
+    tracing::debug!("     updating hdr");
+    write_atomic(hdr_path, &meta_header).await.map_err(|e| format!("failed to update hdr: {e}"))
 }
 
 // - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
@@ -204,6 +314,12 @@ impl Storage for DiskCache {
         // Serialize meta but do NOT write yet — only commit at finish()
         let (meta_internal, meta_header) = meta.serialize()?;
 
+        // Tags are only recorded once the object has been stored, so that a failed write cannot leave them behind
+        let tags = response_tags(meta.response_header());
+        if !tags.is_empty() {
+            tracing::debug!("     tags = {tags:?}");
+        }
+
         // Prepare a temp file for the body
         tracing::debug!("     creating tmp cache dir");
         fs::create_dir_all(&dir).await.ok(); // best-effort
@@ -218,6 +334,9 @@ impl Storage for DiskCache {
             hdr_path,
             meta_internal,
             meta_header,
+            key: key.clone(),
+            tags,
+            tag_index: &self.tags,
             metrics: self.metrics.clone(),
         };
 
@@ -229,7 +348,7 @@ impl Storage for DiskCache {
     async fn purge(
         &'static self,
         key: &CompactCacheKey,
-        _purge_type: PurgeType,
+        purge_type: PurgeType,
         _trace: &SpanHandle,
     ) -> pingora_error::Result<bool> {
         let fn_name = "purge";
@@ -240,6 +359,24 @@ impl Storage for DiskCache {
         let (_hash, dir, body_path, meta_path, hdr_path) = self.path_from_compact_key(key);
         let body_bytes = if let Ok(md) = tokio::fs::metadata(&body_path).await { md.len() } else { 0u64 };
 
+        // Invalidating the primary slot also invalidates every variant stored under it.
+        // Evictions are tracked per variant by the eviction manager, so they only remove the slot being evicted
+        if key.variance.is_none() && matches!(purge_type, PurgeType::Invalidation) {
+            let variant_bytes = self.purge_variants(key, &dir).await;
+
+            if variant_bytes > 0 {
+                tracing::debug!("     Purged {variant_bytes} bytes of secondary variants");
+                self.metrics.evicted_bytes.inc_by(variant_bytes);
+                self.metrics.size_bytes.sub(variant_bytes as i64);
+            }
+        }
+
+        // Pingora removes a key from the eviction manager after purging it, but purges made by this proxy (such as a
+        // purge by tag) call the storage directly. Removing a key twice is harmless
+        if matches!(purge_type, PurgeType::Invalidation) {
+            eviction_manager().remove(key);
+        }
+
         let existed = match tokio::fs::remove_file(&body_path).await {
             Ok(()) => {
                 tracing::debug!("     Purged {body_bytes} bytes");
@@ -258,6 +395,18 @@ impl Storage for DiskCache {
         let _ = std::fs::remove_file(&hdr_path);
         let _ = std::fs::remove_dir(&dir); // Ignore possible error due to races with above fs_remove() calls
 
+        // Once a primary slot has gone together with all its variants, its tags no longer refer to anything
+        if key.variance.is_none() && !dir.exists() {
+            self.tags.forget(key);
+        }
+
+        // Tidy up the variants directory once its last secondary variant has gone
+        if key.variance.is_some()
+            && let Some(variants_dir) = dir.parent()
+        {
+            let _ = std::fs::remove_dir(variants_dir);
+        }
+
         <Self as Trace>::fn_exit(fn_name);
         Ok(existed)
     }
@@ -277,16 +426,9 @@ impl Storage for DiskCache {
         // The body belonging to this metadata must exist
         let exists = if body_path.exists() {
             tracing::debug!("     body exists");
-            let (meta_internal, meta_header) = meta.serialize()?;
-
-            tracing::debug!("     updating meta");
-            if let Err(e) = fs::write(&meta_path, &meta_internal).await {
-                return trace_fn_exit_with_err(fn_name, &format!("failed to update meta: {e}"), None, false);
-            }
 
-            tracing::debug!("     updating hdr");
-            if let Err(e) = fs::write(&hdr_path, &meta_header).await {
-                return trace_fn_exit_with_err(fn_name, &format!("failed to update hdr: {e}"), None, false);
+            if let Err(e) = write_meta(meta, &meta_path, &hdr_path).await {
+                return trace_fn_exit_with_err(fn_name, &e, None, false);
             }
 
             true
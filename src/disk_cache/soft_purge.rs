@@ -0,0 +1,122 @@
+use crate::disk_cache::{
+    tenants::{valid_tenant_name, TenantUsage},
+    write_meta, DiskCache, VARIANTS_DIR,
+};
+
+use pingora_cache::{key::CompactCacheKey, CacheMeta};
+use serde::{Deserialize, Serialize};
+use std::{
+    path::Path,
+    time::{Duration, SystemTime},
+};
+use tokio::fs;
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+/// How a purge treats the objects it selects
+///
+/// A hard purge deletes them, whereas a soft purge only marks them as expired.
+/// A soft-purged object is revalidated with the origin on its next request, but its body is still available to
+/// `stale-if-error` (and `stale-while-revalidate`) if the origin cannot supply a replacement
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PurgeMode {
+    #[default]
+    Hard,
+    Soft,
+}
+
+impl PurgeMode {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "hard" => Some(Self::Hard),
+            "soft" => Some(Self::Soft),
+            _ => None,
+        }
+    }
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+impl DiskCache {
+    /// Mark an object as expired while keeping its body, returning `true` if anything was stored under the key.
+    /// Expiring a primary slot also expires every variant stored under it
+    pub async fn expire(&self, key: &CompactCacheKey) -> bool {
+        let (_hash, dir, ..) = self.path_from_compact_key(key);
+        let mut expired = expire_slot(&dir).await;
+
+        if key.variance.is_none()
+            && let Ok(mut entries) = fs::read_dir(dir.join(VARIANTS_DIR)).await
+        {
+            while let Ok(Some(entry)) = entries.next_entry().await {
+                expired |= expire_slot(&entry.path()).await;
+            }
+        }
+
+        expired
+    }
+
+    /// Mark every object stored for a tenant as expired, returning what was expired
+    pub async fn expire_tenant(&self, tenant: &str) -> Option<TenantUsage> {
+        if !valid_tenant_name(tenant) {
+            return None;
+        }
+
+        let objects = self.tenant_objects(tenant).await?;
+        let mut usage = TenantUsage::new(tenant, &[]);
+
+        for (key, len) in objects {
+            let (_hash, dir, ..) = self.path_from_compact_key(&key);
+            if expire_slot(&dir).await {
+                usage.objects += 1;
+                usage.size_bytes += len;
+            }
+        }
+
+        tracing::info!("Expired {} objects ({} bytes) for tenant {tenant}", usage.objects, usage.size_bytes);
+        Some(usage)
+    }
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// Rewrite the metadata of the object in one slot, exactly as update_meta() does after a revalidation, so that it is no
+// longer fresh. Its creation time and stale windows are kept, as the object itself has not changed
+async fn expire_slot(dir: &Path) -> bool {
+    let (meta_path, hdr_path) = (dir.join("meta"), dir.join("hdr"));
+
+    if !fs::try_exists(dir.join("body")).await.unwrap_or_default() {
+        return false;
+    }
+
+    let meta = match (fs::read(&meta_path).await, fs::read(&hdr_path).await) {
+        (Ok(meta_bin), Ok(hdr_bin)) => match CacheMeta::deserialize(&meta_bin, &hdr_bin) {
+            Ok(meta) => meta,
+            Err(e) => {
+                tracing::warn!("Unable to read metadata in {}: {e}", dir.display());
+                return false;
+            },
+        },
+        _ => return false,
+    };
+
+    // CacheMeta treats fresh_until == now as fresh, so push it into the past
+    let now = SystemTime::now();
+    let fresh_until = meta.fresh_until().min(now.checked_sub(Duration::from_secs(1)).unwrap_or(now));
+
+    let mut expired = CacheMeta::new(
+        fresh_until,
+        meta.created(),
+        meta.stale_while_revalidate_sec(),
+        meta.stale_if_error_sec(),
+        meta.response_header_copy(),
+    );
+    if let Some(variance) = meta.variance() {
+        expired.set_variance(variance);
+    }
+
+    match write_meta(&expired, &meta_path, &hdr_path).await {
+        Ok(()) => true,
+        Err(e) => {
+            tracing::warn!("Unable to expire the object in {}: {e}", dir.display());
+            false
+        },
+    }
+}
@@ -0,0 +1,232 @@
+use crate::{
+    consts::{DEFAULT_TAG_INDEX_SAVE_INTERVAL, TAG_INDEX_FILENAME},
+    disk_cache::{soft_purge::PurgeMode, write_atomic, DiskCache},
+    utils::env_var_or_num,
+};
+
+use async_trait::async_trait;
+use pingora::http::ResponseHeader;
+use pingora_cache::{
+    key::{CacheHashKey, CompactCacheKey},
+    CacheKey,
+};
+use pingora_core::{server::ShutdownWatch, services::background::BackgroundService};
+use serde::Serialize;
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        RwLock,
+    },
+    time::Duration,
+};
+use tokio::time::{interval, MissedTickBehavior};
+
+/// Response headers that assign tags to an object: Fastly's space-separated `Surrogate-Key` and Cloudflare's
+/// comma-separated `Cache-Tag`.
+/// Neither is ever sent to clients
+pub const TAG_HEADERS: [&str; 2] = ["surrogate-key", "cache-tag"];
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+/// The tags carried by a response
+pub fn response_tags(resp: &ResponseHeader) -> Vec<String> {
+    let mut tags: Vec<String> = TAG_HEADERS
+        .iter()
+        .flat_map(|name| resp.headers.get_all(*name))
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(|c: char| c == ',' || c.is_ascii_whitespace()))
+        .filter(|tag| !tag.is_empty())
+        .map(String::from)
+        .collect();
+
+    tags.sort();
+    tags.dedup();
+    tags
+}
+
+/// The outcome of purging a tag
+#[derive(Debug, Serialize)]
+pub struct TagPurge {
+    pub tag: String,
+    pub mode: PurgeMode,
+    /// The number of cache keys carrying the tag
+    pub keys: usize,
+    /// The number of those keys for which a cached object was removed (or expired by a soft purge)
+    pub purged: usize,
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+/// Maps each tag to the primary keys of the objects that carry it
+///
+/// Tags are indexed against the primary key because invalidating a primary slot also removes all of its variants.
+/// The index is held in memory and written to `$CACHE_ROOT/_tag_index.json` every `TAG_INDEX_SAVE_INTERVAL_SECS` if it
+/// has changed, and again at shutdown.
+/// Objects stored after the last save before an unclean shutdown are only indexed again when they are next fetched
+/// from the origin
+#[derive(Default)]
+pub struct TagIndex {
+    inner: RwLock<TagMaps>,
+    changed: AtomicBool,
+}
+
+#[derive(Default)]
+struct TagMaps {
+    by_tag: HashMap<String, HashSet<CompactCacheKey>>,
+    by_key: HashMap<CompactCacheKey, Vec<String>>,
+}
+
+impl TagMaps {
+    fn insert(&mut self, key: CompactCacheKey, tags: Vec<String>) {
+        for tag in &tags {
+            self.by_tag.entry(tag.clone()).or_default().insert(key.clone());
+        }
+        self.by_key.insert(key, tags);
+    }
+
+    fn remove(&mut self, key: &CompactCacheKey) {
+        for tag in self.by_key.remove(key).unwrap_or_default() {
+            if let Some(keys) = self.by_tag.get_mut(&tag) {
+                keys.remove(key);
+                if keys.is_empty() {
+                    self.by_tag.remove(&tag);
+                }
+            }
+        }
+    }
+}
+
+impl TagIndex {
+    /// Load the index saved at the last shutdown, or start with an empty one
+    pub fn load(root: &Path) -> Self {
+        let index = Self::default();
+
+        if let Ok(json) = std::fs::read(root.join(TAG_INDEX_FILENAME)) {
+            match serde_json::from_slice::<Vec<(CompactCacheKey, Vec<String>)>>(&json) {
+                Ok(entries) => {
+                    let mut maps = index.write();
+                    for (key, tags) in entries {
+                        maps.insert(key, tags);
+                    }
+                },
+                Err(e) => tracing::warn!("Ignoring unreadable tag index: {e}"),
+            }
+        }
+
+        index
+    }
+
+    /// Write the index to disk if it has changed since it was last saved
+    pub async fn save_if_changed(&self, root: &Path) -> std::io::Result<()> {
+        if !self.changed.swap(false, Ordering::AcqRel) {
+            return Ok(());
+        }
+
+        let json = {
+            let maps = self.read();
+            let entries: Vec<(&CompactCacheKey, &Vec<String>)> = maps.by_key.iter().collect();
+            serde_json::to_vec(&entries)?
+        };
+
+        let saved = write_atomic(&root.join(TAG_INDEX_FILENAME), &json).await;
+        if saved.is_err() {
+            // Try again next time
+            self.changed.store(true, Ordering::Release);
+        }
+
+        saved
+    }
+
+    // - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+    /// Record the tags of an object that has just been stored.
+    /// A primary object replaces the tags previously recorded for its key, whereas a secondary variant adds to them
+    pub fn record(&self, key: &CacheKey, tags: Vec<String>) {
+        let primary = primary_compact_key(key);
+        let mut maps = self.write();
+
+        let tags = match (key.variance_bin(), maps.by_key.get(&primary)) {
+            (Some(_), Some(existing)) => {
+                let mut merged: Vec<String> = existing.iter().cloned().chain(tags).collect();
+                merged.sort();
+                merged.dedup();
+                merged
+            },
+            _ => tags,
+        };
+
+        maps.remove(&primary);
+        if !tags.is_empty() {
+            maps.insert(primary, tags);
+        }
+        self.changed.store(true, Ordering::Release);
+    }
+
+    /// Forget an object that has been removed from the cache
+    pub fn forget(&self, key: &CompactCacheKey) {
+        self.write().remove(key);
+        self.changed.store(true, Ordering::Release);
+    }
+
+    /// The primary keys of every object carrying a tag
+    pub fn keys_for(&self, tag: &str) -> Vec<CompactCacheKey> {
+        self.read()
+            .by_tag
+            .get(tag)
+            .map(|keys| keys.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    // A panic while the lock was held cannot leave the maps half-updated in a way that matters, so carry on regardless
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, TagMaps> {
+        self.inner.read().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, TagMaps> {
+        self.inner.write().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+/// Save the tag index periodically, and once more when the Pingora server shuts down or upgrades
+pub struct PersistTagIndex {
+    pub cache: &'static DiskCache,
+}
+
+#[async_trait]
+impl BackgroundService for PersistTagIndex {
+    async fn start(&self, mut shutdown: ShutdownWatch) {
+        let period = Duration::from_secs(env_var_or_num(
+            "TAG_INDEX_SAVE_INTERVAL_SECS",
+            DEFAULT_TAG_INDEX_SAVE_INTERVAL.as_secs(),
+        ))
+        .max(Duration::from_secs(1));
+
+        let mut ticks = interval(period);
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                // This is only fired for SIGTERM or SIGQUIT, NOT SIGINT
+                _ = shutdown.changed() => break,
+                _ = ticks.tick() => self.save().await,
+            }
+        }
+
+        self.save().await;
+    }
+}
+
+impl PersistTagIndex {
+    async fn save(&self) {
+        if let Err(e) = self.cache.tags.save_if_changed(&self.cache.root).await {
+            tracing::error!("Failed to save the tag index: {e}");
+        }
+    }
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+fn primary_compact_key(key: &CacheKey) -> CompactCacheKey {
+    let mut compact = key.to_compact();
+    compact.variance = None;
+    compact
+}
@@ -0,0 +1,171 @@
+use crate::disk_cache::{eviction_manager, DiskCache, TENANTS_DIR, VARIANTS_DIR};
+
+use pingora_cache::{
+    eviction::EvictionManager,
+    key::{str2hex, CompactCacheKey},
+};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+/// A tenant name becomes a directory name, so it is restricted to ASCII letters, digits, `-` and `_`
+pub fn valid_tenant_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 64
+        && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+/// How much of the cache one tenant occupies
+#[derive(Debug, Default, Serialize)]
+pub struct TenantUsage {
+    pub tenant: String,
+    pub objects: u64,
+    pub size_bytes: u64,
+}
+
+impl TenantUsage {
+    pub(super) fn new(tenant: &str, objects: &[(CompactCacheKey, u64)]) -> Self {
+        Self {
+            tenant: tenant.to_string(),
+            objects: objects.len() as u64,
+            size_bytes: objects.iter().map(|(_, len)| len).sum(),
+        }
+    }
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+impl DiskCache {
+    /// Objects belonging to the default tenant live directly under the cache root; every other tenant has its own
+    /// subdirectory
+    pub(super) fn tenant_root(&self, tenant: &str) -> PathBuf {
+        if tenant.is_empty() {
+            self.root.clone()
+        } else {
+            self.root.join(TENANTS_DIR).join(tenant)
+        }
+    }
+
+    // - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+    /// The names of all tenants that currently have objects in the cache
+    pub async fn tenants(&self) -> Vec<String> {
+        let mut tenants: Vec<String> = subdirs(&self.root.join(TENANTS_DIR))
+            .await
+            .iter()
+            .filter_map(|dir| dir.file_name().and_then(|name| name.to_str()).map(String::from))
+            .filter(|name| valid_tenant_name(name))
+            .collect();
+
+        tenants.sort();
+        tenants
+    }
+
+    // - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+    /// Count the objects and body bytes stored for a tenant, or `None` if the tenant has nothing in the cache
+    pub async fn tenant_usage(&self, tenant: &str) -> Option<TenantUsage> {
+        if !valid_tenant_name(tenant) {
+            return None;
+        }
+
+        let objects = self.tenant_objects(tenant).await?;
+        Some(TenantUsage::new(tenant, &objects))
+    }
+
+    // - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+    /// Remove every object stored for a tenant, returning what was removed
+    ///
+    /// Each object is also removed from the eviction manager so that it no longer counts towards the cache size
+    pub async fn purge_tenant(&self, tenant: &str) -> Option<TenantUsage> {
+        if !valid_tenant_name(tenant) {
+            return None;
+        }
+
+        let objects = self.tenant_objects(tenant).await?;
+        let usage = TenantUsage::new(tenant, &objects);
+
+        for (key, _) in &objects {
+            eviction_manager().remove(key);
+            if key.variance.is_none() {
+                self.tags.forget(key);
+            }
+        }
+
+        if let Err(e) = fs::remove_dir_all(self.tenant_root(tenant)).await {
+            tracing::warn!("Unable to remove all objects for tenant {tenant}: {e}");
+        }
+
+        self.metrics.purge_attempts.inc();
+        self.metrics.evictions.inc_by(usage.objects);
+        self.metrics.evicted_bytes.inc_by(usage.size_bytes);
+        self.metrics.size_bytes.sub(usage.size_bytes as i64);
+
+        tracing::info!("Purged {} objects ({} bytes) for tenant {tenant}", usage.objects, usage.size_bytes);
+        Some(usage)
+    }
+
+    // - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+    // Walk $TENANT_ROOT/hash[0..2]/hash[2..4]/hash, rebuilding the key of each primary slot and secondary variant from
+    // its directory names
+    pub(super) async fn tenant_objects(&self, tenant: &str) -> Option<Vec<(CompactCacheKey, u64)>> {
+        let tenant_root = self.tenant_root(tenant);
+
+        if !fs::metadata(&tenant_root).await.is_ok_and(|md| md.is_dir()) {
+            return None;
+        }
+
+        let mut objects = Vec::new();
+
+        for shard1 in subdirs(&tenant_root).await {
+            for shard2 in subdirs(&shard1).await {
+                for primary_dir in subdirs(&shard2).await {
+                    let Some(primary) = dir_hash(&primary_dir) else { continue };
+
+                    if let Some(len) = body_len(&primary_dir).await {
+                        objects.push((compact_key(primary, None, tenant), len));
+                    }
+
+                    for variant_dir in subdirs(&primary_dir.join(VARIANTS_DIR)).await {
+                        if let Some(variance) = dir_hash(&variant_dir)
+                            && let Some(len) = body_len(&variant_dir).await
+                        {
+                            objects.push((compact_key(primary, Some(variance), tenant), len));
+                        }
+                    }
+                }
+            }
+        }
+
+        Some(objects)
+    }
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+async fn subdirs(dir: &Path) -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+
+    if let Ok(mut entries) = fs::read_dir(dir).await {
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            if entry.file_type().await.is_ok_and(|t| t.is_dir()) {
+                dirs.push(entry.path());
+            }
+        }
+    }
+
+    dirs
+}
+
+pub(super) fn dir_hash(dir: &Path) -> Option<[u8; 16]> {
+    dir.file_name().and_then(|name| name.to_str()).and_then(str2hex)
+}
+
+async fn body_len(dir: &Path) -> Option<u64> {
+    fs::metadata(dir.join("body")).await.ok().map(|md| md.len())
+}
+
+fn compact_key(primary: [u8; 16], variance: Option<[u8; 16]>, tenant: &str) -> CompactCacheKey {
+    CompactCacheKey {
+        primary,
+        variance: variance.map(Box::new),
+        user_tag: tenant.into(),
+    }
+}
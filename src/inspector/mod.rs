@@ -10,11 +10,15 @@ use tokio::sync::{oneshot, Mutex};
 use warp::reply::Response as WarpResponse;
 
 // - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
-const VERSION_PATH: &'static str = "version";
-const HEALTH_PATH: &'static str = "health";
-const STATS_PATH: &'static str = "stats";
-const METRICS_PATH: &'static str = "metrics";
-const CACHE_CONTENTS_PATH: &'static str = "cache";
+const VERSION_PATH: &str = "version";
+const HEALTH_PATH: &str = "health";
+const STATS_PATH: &str = "stats";
+const METRICS_PATH: &str = "metrics";
+const CACHE_CONTENTS_PATH: &str = "cache";
+const TENANTS_PATH: &str = "tenants";
+const BACKENDS_PATH: &str = "backends";
+const TAGS_PATH: &str = "tags";
+const BANS_PATH: &str = "bans";
 
 // - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
 pub struct InspectorHandle {
@@ -62,9 +66,9 @@ impl BackgroundService for StopInspectorOnShutdown {
 }
 
 // - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
-pub fn start_disk_cache_inspector(addr: std::net::SocketAddr, cache: Arc<&'static DiskCache>) -> Arc<InspectorHandle> {
+pub fn start_disk_cache_inspector(addr: std::net::SocketAddr, cache: &'static DiskCache) -> Arc<InspectorHandle> {
     let (tx, rx) = oneshot::channel::<()>();
-    let routes = build_inspector_routes(cache);
+    let routes = build_inspector_routes(cache, addr.ip().is_loopback());
 
     let th = std::thread::Builder::new()
         .name("disk cache inspector".into())
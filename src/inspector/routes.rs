@@ -1,26 +1,34 @@
 use std::convert::Infallible;
 use crate::{
-    disk_cache::DiskCache,
+    config::edge_config,
+    consts::PURGE_SECRET_HEADER,
+    disk_cache::{bans::BanSpec, soft_purge::PurgeMode, DiskCache},
     inspector::{
-        display_disk_cache::handle_req, CACHE_CONTENTS_PATH, HEALTH_PATH, METRICS_PATH, STATS_PATH, VERSION_PATH,
+        display_disk_cache::handle_req, BACKENDS_PATH, BANS_PATH, CACHE_CONTENTS_PATH, HEALTH_PATH, METRICS_PATH,
+        STATS_PATH, TAGS_PATH, TENANTS_PATH, VERSION_PATH,
     },
+    proxy::purge::purge_cfg,
+    tiered::tiered_cache,
 };
 
 use prometheus::{Encoder, TextEncoder};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::sync::Arc;
-use warp::{http::header, Filter, Reply};
-use crate::disk_cache::cache_statistics::CacheStatistics;
+use warp::{
+    http::{header, StatusCode},
+    reject::Reject,
+    Filter, Rejection, Reply,
+};
+use crate::disk_cache::{cache_statistics::CacheStatistics, tenants::TenantUsage};
 use crate::disk_cache::eviction_manager_cfg;
 
 // - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
 /// Build inspector routes into a single Warp filter tree
 pub fn build_inspector_routes(
-    cache: Arc<&'static DiskCache>,
+    cache: &'static DiskCache,
+    local_only: bool,
 ) -> impl Filter<Extract = (impl Reply,), Error = warp::Rejection> + Clone {
-    let cache_clone1 = cache.clone();
-    let cache_clone2 = cache.clone();
-    let static_cache_ref = warp::any().map(move || cache.clone());
+    let static_cache_ref = warp::any().map(move || cache);
 
     let index = warp::path::end().and(warp::get()).map(|| {
         warp::reply::html(format!(
@@ -34,6 +42,9 @@ pub fn build_inspector_routes(
                          <li><a href="/{STATS_PATH}">Statistics</a></li>
                          <li><a href="/{METRICS_PATH}">Metrics</a></li>
                          <li><a href="/{CACHE_CONTENTS_PATH}">Contents</a></li>
+                         <li><a href="/{TENANTS_PATH}">Tenants</a></li>
+                         <li><a href="/{BACKENDS_PATH}">Backends</a></li>
+                         <li><a href="/{BANS_PATH}">Bans</a></li>
                        </ul>
                      </body>
                    </html>"#
@@ -60,8 +71,8 @@ pub fn build_inspector_routes(
     // GET /stats
     let show_stats = warp::path(STATS_PATH)
         .and(warp::get())
-        .and(warp::any().map(move || cache_clone1.clone()))
-        .and_then(|cache: Arc<&'static DiskCache>| async move {
+        .and(static_cache_ref)
+        .and_then(|cache: &'static DiskCache| async move {
             let cs = CacheStatistics {
                 root: cache.root.clone(),
                 start_time: cache.start_time,
@@ -77,8 +88,8 @@ pub fn build_inspector_routes(
     let show_metrics =
         warp::path(METRICS_PATH)
             .and(warp::get())
-            .and(static_cache_ref.clone())
-            .map(|_cache: Arc<&DiskCache>| {
+            .and(static_cache_ref)
+            .map(|_cache: &DiskCache| {
                 let encoder = TextEncoder::new();
                 let metric_families = prometheus::gather();
                 let mut buffer = Vec::new();
@@ -88,7 +99,7 @@ pub fn build_inspector_routes(
 
     // - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
     // GET /cache
-    let cache_root = Arc::new(cache_clone2.root.clone());
+    let cache_root = Arc::new(cache.root.clone());
     let show_cache = warp::path(CACHE_CONTENTS_PATH)
         .and(warp::path::tail()) // captures "" or "sub/dir/file"
         .and(warp::any().map({
@@ -98,11 +109,192 @@ pub fn build_inspector_routes(
         .and(warp::get().or(warp::head()).unify())
         .and_then(handle_req);
 
+    // - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+    // GET /tenants
+    let list_tenants = warp::path(TENANTS_PATH)
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(static_cache_ref)
+        .and_then(|cache: &'static DiskCache| async move {
+            let mut usage = Vec::new();
+
+            for tenant in cache.tenants().await {
+                if let Some(tenant_usage) = cache.tenant_usage(&tenant).await {
+                    usage.push(tenant_usage);
+                }
+            }
+
+            Ok::<_, Infallible>(warp::reply::json(&usage))
+        });
+
+    // GET /tenants/<tenant>
+    let show_tenant = warp::path(TENANTS_PATH)
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(static_cache_ref)
+        .and_then(|tenant: String, cache: &'static DiskCache| async move {
+            Ok::<_, Infallible>(tenant_reply(cache.tenant_usage(&tenant).await))
+        });
+
+    // DELETE /tenants/<tenant>[?mode=soft]
+    let purge_tenant = warp::path(TENANTS_PATH)
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(warp::delete())
+        .and(purge_permitted(local_only))
+        .and(warp::query::<PurgeQuery>())
+        .and(static_cache_ref)
+        .and_then(|tenant: String, query: PurgeQuery, cache: &'static DiskCache| async move {
+            let usage = match query.mode {
+                PurgeMode::Hard => cache.purge_tenant(&tenant).await,
+                PurgeMode::Soft => cache.expire_tenant(&tenant).await,
+            };
+            Ok::<_, Infallible>(tenant_reply(usage))
+        });
+
+    // - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+    // GET /tags/<tag>
+    let show_tag = warp::path(TAGS_PATH)
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(static_cache_ref)
+        .map(|tag: String, cache: &'static DiskCache| {
+            let keys: Vec<String> = cache.tags.keys_for(&tag).iter().map(|key| key.to_string()).collect();
+            warp::reply::json(&serde_json::json!({ "tag": tag, "keys": keys }))
+        });
+
+    // DELETE /tags/<tag>[?mode=soft]
+    let purge_tag = warp::path(TAGS_PATH)
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(warp::delete())
+        .and(purge_permitted(local_only))
+        .and(warp::query::<PurgeQuery>())
+        .and_then(|tag: String, query: PurgeQuery| async move {
+            Ok::<_, Infallible>(warp::reply::json(&tiered_cache().purge_tag(&tag, query.mode).await))
+        });
+
+    // - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+    // GET /bans
+    let list_bans = warp::path(BANS_PATH)
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(static_cache_ref)
+        .map(|cache: &'static DiskCache| warp::reply::json(&cache.bans.list()));
+
+    // POST /bans
+    let add_ban = warp::path(BANS_PATH)
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(purge_permitted(local_only))
+        .and(warp::body::content_length_limit(16 * 1024))
+        .and(warp::body::json())
+        .and(static_cache_ref)
+        .and_then(|spec: BanSpec, cache: &'static DiskCache| async move {
+            let reply = match cache.bans.add(spec).await {
+                Ok(ban) => warp::reply::with_status(warp::reply::json(&ban), StatusCode::CREATED).into_response(),
+                Err(e) => error_reply(&e, StatusCode::BAD_REQUEST),
+            };
+            Ok::<_, Infallible>(reply)
+        });
+
+    // DELETE /bans/<id>
+    let remove_ban = warp::path(BANS_PATH)
+        .and(warp::path::param::<u64>())
+        .and(warp::path::end())
+        .and(warp::delete())
+        .and(purge_permitted(local_only))
+        .and(static_cache_ref)
+        .and_then(|id: u64, cache: &'static DiskCache| async move {
+            let reply = match cache.bans.remove(id).await {
+                Some(ban) => warp::reply::json(&ban).into_response(),
+                None => error_reply("ban not found", StatusCode::NOT_FOUND),
+            };
+            Ok::<_, Infallible>(reply)
+        });
+
+    // - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+    // GET /backends
+    let show_backends = warp::path(BACKENDS_PATH).and(warp::get()).map(|| {
+        let routes: Vec<_> = edge_config()
+            .routing
+            .routes
+            .iter()
+            .enumerate()
+            .map(|(idx, route)| {
+                serde_json::json!({
+                    "route": idx,
+                    "hosts": route.hosts,
+                    "host_regex": route.host_regex.as_ref().map(|re| re.as_str()),
+                    "backends": route.pool().map(|pool| pool.status()).unwrap_or_default(),
+                })
+            })
+            .collect();
+
+        warp::reply::json(&routes)
+    });
+
     index
         .or(show_version)
         .or(show_health)
         .or(show_stats)
         .or(show_metrics)
         .or(show_cache)
+        .or(list_tenants)
+        .or(show_tenant)
+        .or(purge_tenant)
+        .or(show_tag)
+        .or(purge_tag)
+        .or(list_bans)
+        .or(add_ban)
+        .or(remove_ban)
+        .or(show_backends)
+        .recover(forbidden_reply)
         .with(warp::trace::request())
 }
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// Routes that change the cache are accepted from anyone who can reach the inspector while it only listens on a loopback
+// address. Otherwise, they need the X-Purge-Secret header that authorises a PURGE request from any address
+#[derive(Debug)]
+struct Forbidden;
+impl Reject for Forbidden {}
+
+fn purge_permitted(local_only: bool) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::optional::<String>(PURGE_SECRET_HEADER)
+        .and_then(move |secret: Option<String>| async move {
+            if local_only || purge_cfg().has_secret(secret.as_deref().map(str::as_bytes)) {
+                Ok(())
+            } else {
+                Err(warp::reject::custom(Forbidden))
+            }
+        })
+        .untuple_one()
+}
+
+async fn forbidden_reply(rejection: Rejection) -> Result<warp::reply::Response, Rejection> {
+    match rejection.find::<Forbidden>() {
+        Some(Forbidden) => Ok(error_reply("forbidden", StatusCode::FORBIDDEN)),
+        None => Err(rejection),
+    }
+}
+
+// The purge endpoints delete objects unless asked for a soft purge with ?mode=soft
+#[derive(Deserialize)]
+struct PurgeQuery {
+    #[serde(default)]
+    mode: PurgeMode,
+}
+
+fn tenant_reply(usage: Option<TenantUsage>) -> warp::reply::Response {
+    match usage {
+        Some(usage) => warp::reply::json(&usage).into_response(),
+        None => error_reply("tenant not found", StatusCode::NOT_FOUND),
+    }
+}
+
+fn error_reply(error: &str, status: StatusCode) -> warp::reply::Response {
+    warp::reply::with_status(warp::reply::json(&serde_json::json!({ "error": error })), status).into_response()
+}
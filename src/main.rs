@@ -1,3 +1,4 @@
+mod config;
 mod consts;
 mod disk_cache;
 mod inspector;
@@ -9,18 +10,19 @@ mod tiered;
 mod utils;
 
 use crate::{
-    consts::{DEFAULT_PROXY_PORT_HTTP, DEFAULT_PROXY_PORT_HTTPS},
-    disk_cache::{cache_statistics::PersistCacheOnShutdown, disk_cache, eviction_manager_cfg},
+    config::load_edge_config,
+    consts::{DEFAULT_INSPECTOR_LISTEN_ADDR, DEFAULT_PROXY_PORT_HTTP, DEFAULT_PROXY_PORT_HTTPS},
+    disk_cache::{cache_statistics::PersistCacheOnShutdown, disk_cache, eviction_manager_cfg, tags::PersistTagIndex},
     inspector::{start_disk_cache_inspector, StopInspectorOnShutdown},
     logger::BackgroundLogger,
-    proxy::EdgeCdnProxy,
+    proxy::{origin_pool::start_origin_pools, EdgeCdnProxy},
     statics::*,
-    utils::env_var_or_num,
+    utils::{env_var_or_num, env_var_or_str},
 };
 
 use pingora::prelude::*;
 use pingora_core::server::{configuration::Opt, Server};
-use std::{error::Error, fs::OpenOptions, sync::Arc};
+use std::{error::Error, fs::OpenOptions, net::SocketAddr};
 
 // - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
 fn main() -> Result<(), Box<dyn Error>> {
@@ -50,13 +52,21 @@ fn main() -> Result<(), Box<dyn Error>> {
     );
     server.add_service(logger);
 
+    // Structured configuration must be in place before the proxy handles any requests
+    load_edge_config()?;
+
+    // Origin pools run their active health checks as background services
+    server.add_services(start_origin_pools()?);
+
     let proxy_http_port: u16 = env_var_or_num("PROXY_HTTP_PORT", DEFAULT_PROXY_PORT_HTTP);
     let proxy_https_port: u16 = env_var_or_num("PROXY_HTTPS_PORT", DEFAULT_PROXY_PORT_HTTPS);
-    let mut service = http_proxy_service(&server.configuration, EdgeCdnProxy::new(proxy_http_port, proxy_https_port));
+    let http_listener = SocketAddr::from((IN_ADDR_ANY, proxy_http_port));
+    let https_listener = SocketAddr::from((IN_ADDR_ANY, proxy_https_port));
+    let mut service = http_proxy_service(&server.configuration, EdgeCdnProxy::new(http_listener, https_listener));
 
-    service.add_tcp(&format!("{IN_ADDR_ANY}:{proxy_http_port}"));
+    service.add_tcp(&http_listener.to_string());
     service.add_tls(
-        &format!("{IN_ADDR_ANY}:{proxy_https_port}"),
+        &https_listener.to_string(),
         &format!("{}/server.crt", server_keys_dir()),
         &format!("{}/server.pem", server_keys_dir()),
     )?;
@@ -64,12 +74,18 @@ fn main() -> Result<(), Box<dyn Error>> {
 
     let persist_cache_svc = background_service(
         "persist cache on shutdown",
-        PersistCacheOnShutdown { cache: Arc::new(disk_cache()) },
+        PersistCacheOnShutdown { cache: disk_cache() },
     );
     server.add_service(persist_cache_svc);
 
-    // Start inspector on port 8080
-    let inspector = start_disk_cache_inspector((IN_ADDR_ANY, 8080).into(), Arc::new(disk_cache()));
+    let persist_tags_svc = background_service("persist tag index", PersistTagIndex { cache: disk_cache() });
+    server.add_service(persist_tags_svc);
+
+    // The inspector can change the cache, so by default it is only reachable from this host
+    let inspector_listener: SocketAddr = env_var_or_str("INSPECTOR_LISTEN_ADDR", DEFAULT_INSPECTOR_LISTEN_ADDR)
+        .parse()
+        .map_err(|e| format!("Invalid INSPECTOR_LISTEN_ADDR: {e}"))?;
+    let inspector = start_disk_cache_inspector(inspector_listener, disk_cache());
 
     let stop_inspector_svc = background_service(
         "stop inspector on shutdown",
@@ -83,6 +99,7 @@ fn main() -> Result<(), Box<dyn Error>> {
     );
     tracing::info!("    HTTP  proxy listening on {IN_ADDR_ANY}:{}...", proxy_http_port);
     tracing::info!("    HTTPS proxy listening on {IN_ADDR_ANY}:{}...", proxy_https_port);
+    tracing::info!("    Inspector listening on {inspector_listener}...");
 
     // Run until a SIGINT/SIGTERM/SIGQUIT is received
     // Only SIGTERM and SIGQUIT will trigger a graceful shutdown
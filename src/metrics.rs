@@ -1,4 +1,7 @@
-use prometheus::{register_int_counter, register_int_gauge, IntCounter, IntGauge};
+use prometheus::{
+    register_int_counter, register_int_counter_vec, register_int_gauge, IntCounter, IntCounterVec, IntGauge,
+};
+use std::sync::OnceLock;
 
 // - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
 pub struct CacheMetrics {
@@ -29,3 +32,107 @@ impl CacheMetrics {
         cm
     }
 }
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+pub struct ProxyMetrics {
+    pub revalidations: IntCounter,
+    pub revalidated_not_modified: IntCounter,
+    pub stale_while_revalidate: IntCounter,
+    pub stale_if_error: IntCounter,
+    pub served_by_status: IntCounterVec,
+    pub stored_by_status: IntCounterVec,
+    pub collapsed_requests: IntCounter,
+    pub unknown_host_rejections: IntCounter,
+    pub egress_rejections: IntCounter,
+    pub backend_ejections: IntCounter,
+    pub forwarding_loops: IntCounter,
+    pub purge_requests: IntCounterVec,
+    pub banned_hits: IntCounter,
+    pub cache_admissions: IntCounterVec,
+    pub unsafe_method_invalidations: IntCounter,
+}
+
+impl ProxyMetrics {
+    fn new() -> Self {
+        Self {
+            revalidations: register_int_counter!("revalidations", "Stale cache entries revalidated with the origin")
+                .unwrap(),
+            revalidated_not_modified: register_int_counter!(
+                "revalidated_not_modified",
+                "Revalidations answered with 304 Not Modified"
+            )
+            .unwrap(),
+            stale_while_revalidate: register_int_counter!(
+                "stale_while_revalidate",
+                "Stale responses served while a background refresh runs"
+            )
+            .unwrap(),
+            stale_if_error: register_int_counter!("stale_if_error", "Stale responses served after an origin error")
+                .unwrap(),
+            served_by_status: register_int_counter_vec!(
+                "cache_served_by_status",
+                "Responses served from the cache, by HTTP status",
+                &["status"]
+            )
+            .unwrap(),
+            stored_by_status: register_int_counter_vec!(
+                "cache_stored_by_status",
+                "Responses stored in the cache, by HTTP status",
+                &["status"]
+            )
+            .unwrap(),
+            collapsed_requests: register_int_counter!(
+                "collapsed_requests",
+                "Requests that waited on the cache lock and were served from another request's fill"
+            )
+            .unwrap(),
+            unknown_host_rejections: register_int_counter!(
+                "unknown_host_rejections",
+                "Requests rejected because no route matches their host"
+            )
+            .unwrap(),
+            egress_rejections: register_int_counter!(
+                "egress_rejections",
+                "Origin connections refused because every address of the origin is in a restricted range"
+            )
+            .unwrap(),
+            backend_ejections: register_int_counter!(
+                "backend_ejections",
+                "Origin backends ejected after consecutive connection errors or 5xx responses"
+            )
+            .unwrap(),
+            forwarding_loops: register_int_counter!(
+                "forwarding_loops",
+                "Requests rejected with 508 Loop Detected because they would arrive back at this node"
+            )
+            .unwrap(),
+            purge_requests: register_int_counter_vec!(
+                "purge_requests",
+                "PURGE requests, by outcome",
+                &["result"]
+            )
+            .unwrap(),
+            banned_hits: register_int_counter!(
+                "banned_hits",
+                "Cache hits treated as misses because a ban invalidated the stored object"
+            )
+            .unwrap(),
+            cache_admissions: register_int_counter_vec!(
+                "cache_admissions",
+                "Cacheable responses to cache misses, by whether the admission filter let them into the cache",
+                &["result"]
+            )
+            .unwrap(),
+            unsafe_method_invalidations: register_int_counter!(
+                "unsafe_method_invalidations",
+                "Cached objects invalidated by a successful POST, PUT, DELETE or other unsafe request for their URI"
+            )
+            .unwrap(),
+        }
+    }
+}
+
+static PROXY_METRICS: OnceLock<ProxyMetrics> = OnceLock::new();
+pub fn proxy_metrics() -> &'static ProxyMetrics {
+    PROXY_METRICS.get_or_init(ProxyMetrics::new)
+}
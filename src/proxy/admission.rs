@@ -0,0 +1,124 @@
+use crate::{
+    consts::{DEFAULT_CACHE_ADMISSION_THRESHOLD, DEFAULT_CACHE_ADMISSION_WINDOW},
+    utils::env_var_or_num,
+};
+
+use pingora_cache::key::HashBinary;
+use std::{
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+// The sketch has DEPTH rows of WIDTH counters. Each row is indexed by a different 32-bit slice of the key's hash, so
+// two keys only share an estimate if they collide in every row
+const DEPTH: usize = 4;
+const WIDTH: usize = 1 << 16;
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// An object is only written to disk once it has been requested `threshold` times within `window`.
+// A threshold of 1 admits every cacheable response
+pub struct AdmissionCfg {
+    pub threshold: u8,
+    pub window: Duration,
+}
+
+static ADMISSION_CFG: OnceLock<AdmissionCfg> = OnceLock::new();
+pub fn admission_cfg() -> &'static AdmissionCfg {
+    ADMISSION_CFG.get_or_init(|| AdmissionCfg {
+        threshold: admission_threshold(),
+        window: Duration::from_secs(env_var_or_num(
+            "CACHE_ADMISSION_WINDOW_SECS",
+            DEFAULT_CACHE_ADMISSION_WINDOW.as_secs(),
+        ))
+        .max(Duration::from_secs(1)),
+    })
+}
+
+// The sketch's counters saturate at u8::MAX, so no higher threshold could ever be reached
+fn admission_threshold() -> u8 {
+    let threshold: u32 = env_var_or_num("CACHE_ADMISSION_THRESHOLD", DEFAULT_CACHE_ADMISSION_THRESHOLD.into());
+
+    u8::try_from(threshold).unwrap_or_else(|_| {
+        tracing::warn!("CACHE_ADMISSION_THRESHOLD of {threshold} is too large, so {} is used instead", u8::MAX);
+        u8::MAX
+    })
+    .max(1)
+}
+
+static ADMISSION_FILTER: OnceLock<AdmissionFilter> = OnceLock::new();
+pub fn admission_filter() -> &'static AdmissionFilter {
+    ADMISSION_FILTER.get_or_init(|| AdmissionFilter::new(admission_cfg()))
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+/// Keeps objects that are only requested once off the disk
+///
+/// Every cacheable miss is counted in a count-min sketch keyed by the primary cache key, and the response is only
+/// admitted to the cache once the estimated count reaches the threshold.
+/// A count-min sketch can overestimate (when keys collide in every row) but never underestimates, so an object that
+/// has reached the threshold is always admitted.
+///
+/// Counts are kept in two generations: at the end of each window, the current sketch becomes the previous one and a
+/// new sketch is started.
+/// A key's estimate is the sum of both, so every request within the last window is counted, along with those from the
+/// window before
+pub struct AdmissionFilter {
+    threshold: u8,
+    window: Duration,
+    sketches: Mutex<Sketches>,
+}
+
+struct Sketches {
+    current: Vec<u8>,
+    previous: Vec<u8>,
+    started: Instant,
+}
+
+impl AdmissionFilter {
+    fn new(cfg: &AdmissionCfg) -> Self {
+        // Nothing is counted when everything is admitted, so the counters are never needed
+        let width = if cfg.threshold > 1 { WIDTH } else { 0 };
+
+        Self {
+            threshold: cfg.threshold,
+            window: cfg.window,
+            sketches: Mutex::new(Sketches {
+                current: vec![0; DEPTH * width],
+                previous: vec![0; DEPTH * width],
+                started: Instant::now(),
+            }),
+        }
+    }
+
+    /// Count a cacheable miss for an object, returning `true` if it should now be written to the cache
+    pub fn admit(&self, primary: &HashBinary) -> bool {
+        if self.threshold <= 1 {
+            return true;
+        }
+
+        let mut sketches = self.sketches.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        if sketches.started.elapsed() >= self.window {
+            let Sketches { current, previous, .. } = &mut *sketches;
+            std::mem::swap(current, previous);
+            current.fill(0);
+            sketches.started = Instant::now();
+        }
+
+        let mut estimate = u8::MAX;
+        for slot in slots(primary) {
+            sketches.current[slot] = sketches.current[slot].saturating_add(1);
+            estimate = estimate.min(sketches.current[slot].saturating_add(sketches.previous[slot]));
+        }
+
+        estimate >= self.threshold
+    }
+}
+
+// The counter used for a key in each row
+fn slots(hash: &HashBinary) -> impl Iterator<Item = usize> + '_ {
+    hash.chunks_exact(4).take(DEPTH).enumerate().map(|(row, bytes)| {
+        let column = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize % WIDTH;
+        row * WIDTH + column
+    })
+}
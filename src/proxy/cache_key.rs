@@ -0,0 +1,246 @@
+use crate::config::{edge_config, lookup_host};
+
+use serde::Deserialize;
+use std::collections::HashMap;
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+/// Cache key normalisation rules, with optional per-host overrides
+///
+/// ```json
+/// "cache_key": {
+///   "default": { "query_denylist": ["utm_*", "fbclid", "gclid"] },
+///   "hosts": {
+///     "static.example.com": { "query_allowlist": ["v"], "lowercase_path": true }
+///   }
+/// }
+/// ```
+///
+/// Host names must be given in lowercase.
+/// A host pattern of the form `*.example.com` matches any subdomain of `example.com`, but an exact match always wins.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheKeyConfig {
+    pub default: KeyRules,
+    pub hosts: HashMap<String, KeyRules>,
+}
+
+impl CacheKeyConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        match self.hosts.keys().find(|host| host.chars().any(|c| c.is_ascii_uppercase())) {
+            Some(host) => Err(format!("cache_key host \"{host}\" must be lowercase")),
+            None => Ok(()),
+        }
+    }
+
+    pub fn rules_for(&self, host: &str) -> &KeyRules {
+        lookup_host(&self.hosts, host).unwrap_or(&self.default)
+    }
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+/// How the path and query string of a request are normalised before they become part of the cache key
+///
+/// Query parameter names in the allow and deny lists may end with `*` to match by prefix (for example `utm_*`).
+/// If an allowlist is given, only those parameters are kept; the denylist is then applied to whatever remains.
+///
+/// Every rule is off by default, so an unconfigured host's URIs are cached as requested, except that empty query
+/// parameters (as in `?a=1&&b=2`) and a bare trailing `?` are always dropped, and an empty path becomes `/`.
+/// Sorting parameters and canonicalising their encoding must be enabled explicitly, since some origins treat those
+/// forms differently
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeyRules {
+    pub sort_query: bool,
+    pub query_allowlist: Option<Vec<String>>,
+    pub query_denylist: Vec<String>,
+    pub canonicalize_encoding: bool,
+    pub lowercase_path: bool,
+}
+
+impl KeyRules {
+    fn keeps_param(&self, name: &str) -> bool {
+        let allowed = self
+            .query_allowlist
+            .as_ref()
+            .is_none_or(|allowlist| allowlist.iter().any(|pattern| param_matches(pattern, name)));
+
+        allowed && !self.query_denylist.iter().any(|pattern| param_matches(pattern, name))
+    }
+}
+
+fn param_matches(pattern: &str, name: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => name.starts_with(prefix),
+        None => pattern == name,
+    }
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+/// Normalise the path and query of a request URI according to the rules configured for its host
+///
+/// This is used for every cache key, so lookups and purges of equivalent URIs always resolve to the same object
+pub fn normalize_path_and_query(host: &str, path_and_query: &str) -> String {
+    normalize_with(edge_config().cache_key.rules_for(host), path_and_query)
+}
+
+fn normalize_with(rules: &KeyRules, path_and_query: &str) -> String {
+    let (path, query) = match path_and_query.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (path_and_query, None),
+    };
+
+    let path = if rules.lowercase_path { path.to_lowercase() } else { path.to_string() };
+    let mut normalized = if rules.canonicalize_encoding { canonicalize_percent_encoding(&path) } else { path };
+
+    if normalized.is_empty() {
+        normalized.push('/');
+    }
+
+    let mut params: Vec<String> = query
+        .unwrap_or_default()
+        .split('&')
+        .filter(|param| !param.is_empty())
+        .map(|param| if rules.canonicalize_encoding { canonicalize_percent_encoding(param) } else { param.to_string() })
+        .filter(|param| rules.keeps_param(param_name(param)))
+        .collect();
+
+    // A stable sort by name keeps repeated parameters in their original relative order
+    if rules.sort_query {
+        params.sort_by(|a, b| param_name(a).cmp(param_name(b)));
+    }
+
+    if !params.is_empty() {
+        normalized.push('?');
+        normalized.push_str(&params.join("&"));
+    }
+
+    normalized
+}
+
+fn param_name(param: &str) -> &str {
+    param.split_once('=').map_or(param, |(name, _)| name)
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// RFC 3986 §6.2.2: percent-encoded unreserved characters are decoded, and all other percent-encodings use uppercase hex.
+// Anything that is not a valid percent-encoding is left untouched
+fn canonicalize_percent_encoding(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let is_escape = bytes[i] == b'%'
+            && i + 2 < bytes.len()
+            && bytes[i + 1].is_ascii_hexdigit()
+            && bytes[i + 2].is_ascii_hexdigit();
+
+        if !is_escape {
+            out.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+
+        let decoded = (hex_value(bytes[i + 1]) << 4) | hex_value(bytes[i + 2]);
+        if decoded.is_ascii_alphanumeric() || matches!(decoded, b'-' | b'.' | b'_' | b'~') {
+            out.push(decoded);
+        } else {
+            out.extend_from_slice(format!("%{decoded:02X}").as_bytes());
+        }
+        i += 3;
+    }
+
+    // Only ASCII bytes have been substituted, so the result is still valid UTF-8
+    String::from_utf8(out).unwrap_or_else(|_| s.to_string())
+}
+
+fn hex_value(digit: u8) -> u8 {
+    match digit {
+        b'0'..=b'9' => digit - b'0',
+        b'a'..=b'f' => digit - b'a' + 10,
+        _ => digit - b'A' + 10,
+    }
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn default_rules_keep_the_uri_as_requested() {
+        let rules = KeyRules::default();
+
+        assert_eq!(normalize_with(&rules, "/A%2fb?z=1&a=%7e"), "/A%2fb?z=1&a=%7e");
+        assert_eq!(normalize_with(&rules, ""), "/");
+        assert_eq!(normalize_with(&rules, "?"), "/");
+    }
+
+    #[test]
+    fn empty_params_are_dropped_but_empty_values_are_kept() {
+        let rules = KeyRules::default();
+
+        assert_eq!(normalize_with(&rules, "/p?&a=&&b&"), "/p?a=&b");
+    }
+
+    #[test]
+    fn sorting_is_stable_for_repeated_params() {
+        let rules = KeyRules { sort_query: true, ..Default::default() };
+
+        assert_eq!(normalize_with(&rules, "/p?b=2&a=2&b=1&a=1"), "/p?a=2&a=1&b=2&b=1");
+        // Only the name takes part in the ordering
+        assert_eq!(normalize_with(&rules, "/p?ab=1&a=2"), "/p?a=2&ab=1");
+    }
+
+    #[test]
+    fn allowlist_then_denylist() {
+        let rules = KeyRules {
+            query_allowlist: Some(list(&["v", "utm_*"])),
+            query_denylist: list(&["utm_source"]),
+            ..Default::default()
+        };
+
+        assert_eq!(normalize_with(&rules, "/p?x=1&v=2&utm_source=a&utm_medium=b&v=3"), "/p?v=2&utm_medium=b&v=3");
+        assert_eq!(normalize_with(&rules, "/p?x=1"), "/p");
+    }
+
+    #[test]
+    fn denylist_prefix_patterns() {
+        let rules = KeyRules { query_denylist: list(&["utm_*", "fbclid"]), ..Default::default() };
+
+        assert_eq!(normalize_with(&rules, "/p?utm_=1&fbclid=2&fbclid_x=3&q=4"), "/p?fbclid_x=3&q=4");
+    }
+
+    #[test]
+    fn canonical_encoding_decodes_unreserved_and_uppercases_the_rest() {
+        let rules = KeyRules { canonicalize_encoding: true, ..Default::default() };
+
+        assert_eq!(normalize_with(&rules, "/%7euser/%41%2f%2Fb?q=%3d%2d&r=%zz%4"), "/~user/A%2F%2Fb?q=%3D-&r=%zz%4");
+    }
+
+    #[test]
+    fn encoded_param_names_match_lists_once_canonicalised() {
+        let rules = KeyRules { canonicalize_encoding: true, query_denylist: list(&["utm_*"]), ..Default::default() };
+
+        assert_eq!(normalize_with(&rules, "/p?utm%5Fsource=a&q=1"), "/p?q=1");
+    }
+
+    #[test]
+    fn reserved_characters_are_not_decoded_or_split() {
+        let rules = KeyRules { sort_query: true, canonicalize_encoding: true, ..Default::default() };
+
+        // An encoded `&` or `=` is part of a value, not a separator
+        assert_eq!(normalize_with(&rules, "/p?b=x%26a%3d1&a=y=z"), "/p?a=y=z&b=x%26a%3D1");
+    }
+
+    #[test]
+    fn lowercase_path_leaves_the_query_alone() {
+        let rules = KeyRules { lowercase_path: true, ..Default::default() };
+
+        assert_eq!(normalize_with(&rules, "/Images/Logo.PNG?V=A"), "/images/logo.png?V=A");
+    }
+}
@@ -0,0 +1,46 @@
+use crate::{
+    consts::{DEFAULT_CACHE_LOCK_AGE_TIMEOUT, DEFAULT_CACHE_LOCK_WAIT_TIMEOUT},
+    utils::env_var_or_num,
+};
+
+use pingora_cache::{lock::CacheLock, CacheOptionOverrides};
+use std::{sync::OnceLock, time::Duration};
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// The age timeout limits how long the request filling the cache may hold the lock before waiting readers give up on it.
+// The wait timeout limits how long any one reader will wait in total.
+// Readers that time out go to the origin without caching the response
+pub struct CacheLockCfg {
+    pub age_timeout: Duration,
+    pub wait_timeout: Duration,
+}
+
+static CACHE_LOCK_CFG: OnceLock<CacheLockCfg> = OnceLock::new();
+pub fn cache_lock_cfg() -> &'static CacheLockCfg {
+    CACHE_LOCK_CFG.get_or_init(|| CacheLockCfg {
+        age_timeout: Duration::from_secs(env_var_or_num(
+            "CACHE_LOCK_AGE_TIMEOUT_SECS",
+            DEFAULT_CACHE_LOCK_AGE_TIMEOUT.as_secs(),
+        )),
+        wait_timeout: Duration::from_secs(env_var_or_num(
+            "CACHE_LOCK_WAIT_TIMEOUT_SECS",
+            DEFAULT_CACHE_LOCK_WAIT_TIMEOUT.as_secs(),
+        )),
+    })
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// Concurrent requests for the same missing or expired object are collapsed onto a single origin fetch.
+// Pingora also only serves stale-while-revalidate from the request that holds the cache lock for an expired entry,
+// since that request is the one that spawns the background refresh
+static CACHE_LOCK: OnceLock<CacheLock> = OnceLock::new();
+pub fn cache_lock() -> &'static CacheLock {
+    CACHE_LOCK.get_or_init(|| CacheLock::new(cache_lock_cfg().age_timeout))
+}
+
+// The wait timeout is passed to Pingora per request rather than being a property of the lock itself
+pub fn cache_lock_overrides() -> CacheOptionOverrides {
+    let mut overrides = CacheOptionOverrides::default();
+    overrides.wait_timeout = Some(cache_lock_cfg().wait_timeout);
+    overrides
+}
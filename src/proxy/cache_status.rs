@@ -0,0 +1,191 @@
+use crate::{
+    consts::CACHE_STATUS_NAME,
+    proxy::{context::EdgeCtx, purge::purge_cfg},
+    tiered::CacheTier,
+};
+
+use pingora::prelude::Session;
+use pingora_cache::CachePhase;
+use std::{fmt::Write, time::SystemTime};
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+/// Why a request was forwarded to the origin (the `fwd` parameter of RFC 9211 §2.2)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ForwardReason {
+    /// The cache was not used for this request
+    Bypass,
+    /// Nothing was stored under this URI
+    UriMiss,
+    /// The stored response was stale
+    Stale,
+    /// The stored response was fresh, but the client's directives demanded revalidation
+    Request,
+    /// The stored response has been invalidated by a ban
+    Banned,
+}
+
+impl ForwardReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ForwardReason::Bypass => "bypass",
+            ForwardReason::UriMiss => "uri-miss",
+            ForwardReason::Stale => "stale",
+            ForwardReason::Request => "request",
+            // RFC 9211 has no dedicated value, but a banned object is no longer a usable match for the request
+            ForwardReason::Banned => "miss",
+        }
+    }
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+/// How the cache handled a request
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CacheOutcome {
+    /// Served from the cache without contacting the origin
+    Hit,
+    /// A stale response was served from the cache, either during stale-while-revalidate or after an origin error
+    StaleHit,
+    /// The origin confirmed with `304 Not Modified` that the stored response is still valid
+    Revalidated,
+    /// The response was fetched from the origin
+    Forwarded(ForwardReason),
+}
+
+impl CacheOutcome {
+    pub fn is_hit(&self) -> bool {
+        matches!(self, CacheOutcome::Hit | CacheOutcome::StaleHit)
+    }
+
+    /// Was the body served from the cache, even if the origin had to confirm it first?
+    pub fn served_from_cache(&self) -> bool {
+        matches!(self, CacheOutcome::Hit | CacheOutcome::StaleHit | CacheOutcome::Revalidated)
+    }
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+/// Everything reported in the `Cache-Status` response header (RFC 9211)
+#[derive(Debug)]
+pub struct CacheStatus {
+    pub outcome: CacheOutcome,
+    /// The status code returned by the origin, if it was contacted
+    pub fwd_status: Option<u16>,
+    /// Remaining freshness of the stored response in seconds; negative once it is stale
+    pub ttl: Option<i64>,
+    /// Whether the response was written to (or refreshed in) the cache
+    pub stored: bool,
+    /// Whether this request waited on the cache lock and was then served from another request's fill
+    pub collapsed: bool,
+    /// The cache key, which is internal, so only reported to clients that would be allowed to purge it
+    pub key: Option<String>,
+    /// The `TieredStorage` tier that served a hit
+    pub tier: Option<CacheTier>,
+}
+
+impl CacheStatus {
+    pub fn from_session(session: &Session, ctx: &EdgeCtx) -> Self {
+        let phase = session.cache.phase();
+        let forwarded = |default| CacheOutcome::Forwarded(ctx.cache_fwd.unwrap_or(default));
+
+        let outcome = match phase {
+            CachePhase::Hit => CacheOutcome::Hit,
+            CachePhase::Stale | CachePhase::StaleUpdating => CacheOutcome::StaleHit,
+            CachePhase::Revalidated | CachePhase::RevalidatedNoCache(_) => CacheOutcome::Revalidated,
+            CachePhase::Miss => forwarded(ForwardReason::UriMiss),
+            CachePhase::Expired => forwarded(ForwardReason::Stale),
+            CachePhase::Disabled(_) | CachePhase::Bypass | CachePhase::Uninit | CachePhase::CacheKey => {
+                forwarded(ForwardReason::Bypass)
+            },
+        };
+        let stored = matches!(phase, CachePhase::Miss | CachePhase::Expired | CachePhase::Revalidated);
+
+        // CacheMeta is only available in the phases in which an object was found or is being stored
+        let meta = match phase {
+            CachePhase::Hit
+            | CachePhase::Stale
+            | CachePhase::StaleUpdating
+            | CachePhase::Revalidated
+            | CachePhase::RevalidatedNoCache(_)
+            | CachePhase::Miss
+            | CachePhase::Expired => session.cache.maybe_cache_meta(),
+            _ => None,
+        };
+        let ttl = meta.map(|meta| match meta.fresh_until().duration_since(SystemTime::now()) {
+            Ok(remaining) => remaining.as_secs() as i64,
+            Err(e) => -(e.duration().as_secs() as i64),
+        });
+
+        Self {
+            outcome,
+            fwd_status: if outcome.is_hit() { None } else { ctx.upstream_status },
+            ttl,
+            stored,
+            // A reader that timed out on the lock went to the origin on its own, so was not collapsed
+            collapsed: session.cache.lock_duration().is_some() && outcome.is_hit(),
+            key: ctx.cache_key.clone().filter(|_| purge_cfg().permits(session)),
+            tier: if outcome.is_hit() { ctx.cache_tier } else { None },
+        }
+    }
+
+    /// Format as a `Cache-Status` field value, for example `edge-cdn-store; fwd=uri-miss; fwd-status=200; stored`
+    pub fn header_value(&self) -> String {
+        let mut value = String::from(CACHE_STATUS_NAME);
+
+        match self.outcome {
+            CacheOutcome::Hit | CacheOutcome::StaleHit => value.push_str("; hit"),
+            CacheOutcome::Revalidated => value.push_str("; fwd=stale"),
+            CacheOutcome::Forwarded(reason) => {
+                let _ = write!(value, "; fwd={}", reason.as_str());
+            },
+        }
+
+        if let Some(status) = self.fwd_status {
+            let _ = write!(value, "; fwd-status={status}");
+        }
+
+        if let Some(ttl) = self.ttl {
+            let _ = write!(value, "; ttl={ttl}");
+        }
+
+        if self.stored {
+            value.push_str("; stored");
+        }
+
+        if self.collapsed {
+            value.push_str("; collapsed");
+        }
+
+        if let Some(key) = &self.key {
+            let _ = write!(value, "; key={}", sf_string(key));
+        }
+
+        if let Some(tier) = self.tier {
+            let _ = write!(value, "; detail={}", tier.as_str());
+        }
+
+        value
+    }
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// RFC 8941 §3.3.3: a structured field string is quoted, with only `"` and `\` escaped and printable ASCII allowed.
+// Any other byte is percent-encoded
+fn sf_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+
+    for b in s.bytes() {
+        match b {
+            b'"' | b'\\' => {
+                quoted.push('\\');
+                quoted.push(b as char);
+            },
+            b' '..=b'~' => quoted.push(b as char),
+            _ => {
+                let _ = write!(quoted, "%{b:02X}");
+            },
+        }
+    }
+
+    quoted.push('"');
+    quoted
+}
@@ -0,0 +1,194 @@
+use crate::{
+    consts::DEFAULT_CACHE_AUTHENTICATED_CONTENT,
+    proxy::{freshness::has_explicit_expiry, vary::varies_on_everything},
+    utils::env_var_or_num,
+};
+
+use pingora::http::{Method, RequestHeader, ResponseHeader, StatusCode};
+use pingora_cache::{
+    cache_control::{CacheControl, InterpretCacheControl},
+    NoCacheReason,
+};
+use std::sync::OnceLock;
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// Responses to requests carrying credentials are only stored if the operator has opted in
+pub struct CacheabilityCfg {
+    pub cache_authenticated_content: bool,
+}
+
+static CACHEABILITY_CFG: OnceLock<CacheabilityCfg> = OnceLock::new();
+pub fn cacheability_cfg() -> &'static CacheabilityCfg {
+    CACHEABILITY_CFG.get_or_init(|| CacheabilityCfg {
+        cache_authenticated_content: env_var_or_num(
+            "CACHE_AUTHENTICATED_CONTENT",
+            DEFAULT_CACHE_AUTHENTICATED_CONTENT,
+        ),
+    })
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+/// Whether the cache may be used for a request with this method (RFC 9111 §3).
+/// Only `GET` and `HEAD` are looked up or stored; a response to `HEAD` is served from the stored `GET` response
+pub fn cacheable_method(method: &Method) -> bool {
+    method == Method::GET || method == Method::HEAD
+}
+
+/// Whether a successful response to a request with this method invalidates the stored response for its URI
+/// (RFC 9111 §4.4).
+/// Every method other than the safe methods of RFC 9110 §9.2.1 may change the resource on the origin
+pub fn invalidates_stored(method: &Method) -> bool {
+    !matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE)
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// RFC 9110 §15.1: responses with these status codes may be cached without explicit freshness information.
+// 206 is deliberately absent because Pingora always fetches and stores the full object
+const HEURISTICALLY_CACHEABLE: [StatusCode; 11] = [
+    StatusCode::OK,
+    StatusCode::NON_AUTHORITATIVE_INFORMATION,
+    StatusCode::NO_CONTENT,
+    StatusCode::MULTIPLE_CHOICES,
+    StatusCode::MOVED_PERMANENTLY,
+    StatusCode::PERMANENT_REDIRECT,
+    StatusCode::NOT_FOUND,
+    StatusCode::METHOD_NOT_ALLOWED,
+    StatusCode::GONE,
+    StatusCode::URI_TOO_LONG,
+    StatusCode::NOT_IMPLEMENTED,
+];
+
+// Any other final status may only be stored if the origin gives it an explicit lifetime or marks it public
+fn status_storable(resp: &ResponseHeader, cc: Option<&CacheControl>) -> bool {
+    let status = resp.status;
+
+    if status.is_informational() || status == StatusCode::PARTIAL_CONTENT || status == StatusCode::NOT_MODIFIED {
+        return false;
+    }
+
+    HEURISTICALLY_CACHEABLE.contains(&status) || has_explicit_expiry(resp, cc) || cc.is_some_and(|cc| cc.public())
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+/// Decide whether a shared cache may store a response (RFC 9111 §3)
+///
+/// A response is refused if:
+///
+///   * The request method is neither `GET` nor `HEAD`
+///   * Its status code is neither heuristically cacheable nor given an explicit lifetime or `public` by the origin
+///   * It carries `Cache-Control: no-store` or an unqualified `Cache-Control: private`
+///   * It carries `Vary: *`
+///   * The request carried `Authorization`, unless the operator has opted in to caching authenticated content and the
+///     response explicitly allows it with `public` or `s-maxage`
+///   * It still carries `Set-Cookie` once any headers named by `private="..."` or `no-cache="..."` have been removed
+///
+/// An unqualified `no-cache` does not prevent storage; it gives the response a freshness lifetime of zero so that it
+/// is revalidated before every reuse.
+///
+/// On success, the header to be stored is returned with the fields named by `private="..."` and `no-cache="..."`
+/// removed
+pub fn shared_cacheability(
+    req: &RequestHeader,
+    resp: &ResponseHeader,
+    cc: Option<&CacheControl>,
+    cfg: &CacheabilityCfg,
+) -> Result<ResponseHeader, NoCacheReason> {
+    if !cacheable_method(&req.method) {
+        return Err(NoCacheReason::Custom("request method not cacheable"));
+    }
+
+    if !status_storable(resp, cc) {
+        return Err(NoCacheReason::Custom("status not cacheable"));
+    }
+
+    if cc.is_some_and(|cc| cc.no_store()) {
+        return Err(NoCacheReason::Custom("Cache-Control: no-store"));
+    }
+
+    if cc.is_some_and(|cc| cc.private()) {
+        return Err(NoCacheReason::Custom("Cache-Control: private"));
+    }
+
+    if varies_on_everything(resp) {
+        return Err(NoCacheReason::Custom("Vary: *"));
+    }
+
+    if req.headers.contains_key("authorization") {
+        let explicitly_shareable = cc.is_some_and(|cc| cc.public() || cc.has_key("s-maxage"));
+
+        if !cfg.cache_authenticated_content {
+            return Err(NoCacheReason::Custom("request carries Authorization"));
+        }
+
+        if !explicitly_shareable {
+            return Err(NoCacheReason::Custom("request carries Authorization without public or s-maxage"));
+        }
+    }
+
+    let mut stored = resp.clone();
+
+    if let Some(cc) = cc {
+        cc.strip_private_headers(&mut stored);
+    }
+
+    // Cookies are specific to one client, so they must never be replayed to another
+    if stored.headers.contains_key("set-cookie") {
+        return Err(NoCacheReason::Custom("response carries Set-Cookie"));
+    }
+
+    Ok(stored)
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CFG: CacheabilityCfg = CacheabilityCfg { cache_authenticated_content: false };
+
+    fn request(method: Method) -> RequestHeader {
+        RequestHeader::build(method, b"/page", None).unwrap()
+    }
+
+    fn response() -> ResponseHeader {
+        let mut resp = ResponseHeader::build(200, None).unwrap();
+        resp.insert_header("cache-control", "public, max-age=3600").unwrap();
+        resp
+    }
+
+    fn storable(method: Method) -> bool {
+        let resp = response();
+        let cc = CacheControl::from_resp_headers(&resp);
+        shared_cacheability(&request(method), &resp, cc.as_ref(), &CFG).is_ok()
+    }
+
+    #[test]
+    fn post_is_neither_served_from_cache_nor_stored() {
+        assert!(!cacheable_method(&Method::POST));
+        assert!(!storable(Method::POST));
+        assert!(invalidates_stored(&Method::POST));
+    }
+
+    #[test]
+    fn get_and_head_use_the_cache() {
+        for method in [Method::GET, Method::HEAD] {
+            assert!(cacheable_method(&method));
+            assert!(storable(method.clone()));
+            assert!(!invalidates_stored(&method));
+        }
+    }
+
+    #[test]
+    fn only_unsafe_methods_invalidate() {
+        for method in [Method::PUT, Method::DELETE, Method::PATCH, Method::from_bytes(b"LINK").unwrap()] {
+            assert!(!cacheable_method(&method));
+            assert!(!storable(method.clone()));
+            assert!(invalidates_stored(&method));
+        }
+
+        for method in [Method::OPTIONS, Method::TRACE] {
+            assert!(!cacheable_method(&method));
+            assert!(!invalidates_stored(&method));
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use crate::{
+    consts::DEFAULT_IGNORE_CLIENT_DIRECTIVES,
+    proxy::{freshness::current_age, surrogate_control::edge_cache_control},
+    utils::env_var_or_num,
+};
+
+use pingora::http::RequestHeader;
+use pingora_cache::{cache_control::CacheControl, CacheMeta};
+use std::{
+    sync::OnceLock,
+    time::{Duration, SystemTime},
+};
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// Operators can ignore request directives entirely if clients abuse them to bypass the cache
+pub struct ClientDirectivesCfg {
+    pub ignore_client_directives: bool,
+}
+
+static CLIENT_DIRECTIVES_CFG: OnceLock<ClientDirectivesCfg> = OnceLock::new();
+pub fn client_directives_cfg() -> &'static ClientDirectivesCfg {
+    CLIENT_DIRECTIVES_CFG.get_or_init(|| ClientDirectivesCfg {
+        ignore_client_directives: env_var_or_num("CACHE_IGNORE_CLIENT_DIRECTIVES", DEFAULT_IGNORE_CLIENT_DIRECTIVES),
+    })
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+/// The request `Cache-Control` directives a client can use to constrain what the cache returns (RFC 9111 §5.2.1)
+///
+/// `max-stale` without a value means the client will accept a stale response of any staleness.
+/// When the request has no `Cache-Control` header, `Pragma: no-cache` is treated as `Cache-Control: no-cache`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ClientDirectives {
+    pub no_cache: bool,
+    pub max_age: Option<Duration>,
+    pub max_stale: Option<Duration>,
+    pub min_fresh: Option<Duration>,
+    pub only_if_cached: bool,
+}
+
+impl ClientDirectives {
+    pub fn from_request(req: &RequestHeader) -> Self {
+        let Some(cc) = CacheControl::from_req_headers(req) else {
+            return Self {
+                no_cache: pragma_no_cache(req),
+                ..Self::default()
+            };
+        };
+
+        Self {
+            no_cache: cc.has_key("no-cache"),
+            max_age: delta_seconds(&cc, "max-age"),
+            max_stale: match cc.directives.get("max-stale") {
+                Some(Some(_)) => delta_seconds(&cc, "max-stale"),
+                Some(None) => Some(Duration::MAX),
+                None => None,
+            },
+            min_fresh: delta_seconds(&cc, "min-fresh"),
+            only_if_cached: cc.only_if_cached(),
+        }
+    }
+
+    /// Must a fresh cache entry be revalidated with the origin before it can be returned to this client?
+    ///
+    /// `no-cache` and `max-age=0` always force revalidation.
+    /// Otherwise, `max-age` rejects entries older than the client will accept, and `min-fresh` rejects entries that
+    /// will not stay fresh for long enough.
+    pub fn requires_revalidation(&self, meta: &CacheMeta, now: SystemTime) -> bool {
+        if self.no_cache || self.max_age.is_some_and(|max_age| max_age.is_zero()) {
+            return true;
+        }
+
+        let too_old = self.max_age.is_some_and(|max_age| current_age(meta, now) > max_age);
+        let too_close_to_expiry = self.min_fresh.is_some_and(|min_fresh| {
+            meta.fresh_until().duration_since(now).unwrap_or_default() < min_fresh
+        });
+
+        too_old || too_close_to_expiry
+    }
+
+    /// May a stale cache entry be returned to this client?
+    ///
+    /// The origin's `stale-while-revalidate` and `stale-if-error` windows decide whether stale content may be served
+    /// at all, and Pingora has already checked that the entry is within them.
+    /// These directives can only narrow that decision: Pingora gives `cache_hit_filter()` no way to serve an entry
+    /// outside the origin's windows, so `max-stale` cannot extend them.
+    ///
+    /// `no-cache` and `min-fresh` rule out stale content altogether.
+    /// `max-stale=N` accepts the entry if it has been stale for no more than N seconds, and a bare `max-stale` accepts
+    /// it however stale it is, unless the stored response carries `must-revalidate` or `proxy-revalidate`
+    /// (RFC 9111 §4.2.4).
+    /// Otherwise, `max-age` limits how old the entry may be.
+    pub fn accepts_stale(&self, meta: &CacheMeta, now: SystemTime) -> bool {
+        if self.no_cache || self.min_fresh.is_some() {
+            return false;
+        }
+
+        if let Some(max_stale) = self.max_stale
+            && !must_revalidate(meta)
+        {
+            return staleness(meta, now) <= max_stale;
+        }
+
+        self.max_age.is_none_or(|max_age| current_age(meta, now) <= max_age)
+    }
+}
+
+// How long ago the entry stopped being fresh
+fn staleness(meta: &CacheMeta, now: SystemTime) -> Duration {
+    now.duration_since(meta.fresh_until()).unwrap_or_default()
+}
+
+// The stored response forbids serving it stale on the client's say-so
+fn must_revalidate(meta: &CacheMeta) -> bool {
+    edge_cache_control(meta.response_header())
+        .is_some_and(|cc| cc.has_key("must-revalidate") || cc.has_key("proxy-revalidate"))
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// Malformed values are ignored, as if the directive were absent
+fn delta_seconds(cc: &CacheControl, key: &str) -> Option<Duration> {
+    match cc.directives.get(key) {
+        Some(Some(value)) => value.parse_as_delta_seconds().ok().map(|secs| Duration::from_secs(secs as u64)),
+        _ => None,
+    }
+}
+
+// RFC 9111 §5.4: Pragma is deprecated, but "no-cache" must still be honoured when Cache-Control is absent
+fn pragma_no_cache(req: &RequestHeader) -> bool {
+    req.headers
+        .get_all("pragma")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|directive| directive.trim().eq_ignore_ascii_case("no-cache"))
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pingora::http::{Method, ResponseHeader};
+
+    const HOUR: Duration = Duration::from_secs(3600);
+
+    fn directives(cache_control: &str) -> ClientDirectives {
+        let mut req = RequestHeader::build(Method::GET, b"/", None).unwrap();
+        req.insert_header("cache-control", cache_control).unwrap();
+        ClientDirectives::from_request(&req)
+    }
+
+    // An entry stored two hours ago with a lifetime of one hour, so it has been stale for an hour
+    fn stale_entry(now: SystemTime, cache_control: &str) -> CacheMeta {
+        let mut resp = ResponseHeader::build(200, None).unwrap();
+        resp.insert_header("cache-control", cache_control).unwrap();
+        CacheMeta::new(now - HOUR, now - 2 * HOUR, 0, 0, resp)
+    }
+
+    #[test]
+    fn max_stale_value_limits_staleness() {
+        let now = SystemTime::now();
+        let meta = stale_entry(now, "max-age=3600");
+
+        assert!(!directives("max-stale=1").accepts_stale(&meta, now));
+        assert!(!directives("max-stale=3599").accepts_stale(&meta, now));
+        assert!(directives("max-stale=3600").accepts_stale(&meta, now));
+        assert!(directives("max-stale=86400").accepts_stale(&meta, now));
+    }
+
+    #[test]
+    fn bare_max_stale_accepts_any_staleness() {
+        let now = SystemTime::now();
+        let meta = stale_entry(now, "max-age=3600");
+
+        assert_eq!(directives("max-stale").max_stale, Some(Duration::MAX));
+        assert!(directives("max-stale").accepts_stale(&meta, now));
+    }
+
+    #[test]
+    fn max_stale_does_not_override_must_revalidate() {
+        let now = SystemTime::now();
+        let client = directives("max-stale, max-age=60");
+
+        assert!(client.accepts_stale(&stale_entry(now, "max-age=3600"), now));
+        assert!(!client.accepts_stale(&stale_entry(now, "max-age=3600, must-revalidate"), now));
+        assert!(!client.accepts_stale(&stale_entry(now, "max-age=3600, proxy-revalidate"), now));
+    }
+
+    #[test]
+    fn max_stale_is_ignored_when_malformed() {
+        let now = SystemTime::now();
+        let meta = stale_entry(now, "max-age=3600");
+
+        assert_eq!(directives("max-stale=soon").max_stale, None);
+        assert!(directives("max-stale=soon").accepts_stale(&meta, now));
+    }
+
+    #[test]
+    fn no_cache_and_min_fresh_reject_stale_content() {
+        let now = SystemTime::now();
+        let meta = stale_entry(now, "max-age=3600");
+
+        assert!(!directives("no-cache, max-stale").accepts_stale(&meta, now));
+        assert!(!directives("min-fresh=1, max-stale").accepts_stale(&meta, now));
+    }
+
+    #[test]
+    fn max_age_limits_stale_content_without_max_stale() {
+        let now = SystemTime::now();
+        let meta = stale_entry(now, "max-age=3600");
+
+        assert!(!directives("max-age=60").accepts_stale(&meta, now));
+        assert!(directives("max-age=86400").accepts_stale(&meta, now));
+    }
+}
@@ -0,0 +1,39 @@
+use crate::{
+    proxy::{
+        cache_status::ForwardReason, client_directives::ClientDirectives, peer_options::UpstreamConnection,
+        routing::Route,
+    },
+    tiered::CacheTier,
+};
+
+use pingora::http::ResponseHeader;
+use pingora_cache::key::CompactCacheKey;
+use std::net::SocketAddr;
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+/// Per-request state shared between the `ProxyHttp` filters
+#[derive(Default)]
+pub struct EdgeCtx {
+    /// Headers of a `304 Not Modified` received while revalidating a stale cache entry
+    pub not_modified: Option<ResponseHeader>,
+    /// The request's own caching directives, left at their defaults when the cache is disabled or they are ignored
+    pub client_directives: ClientDirectives,
+    /// The key of the stored object to invalidate if this request, which used an unsafe method, succeeds
+    pub invalidates: Option<CompactCacheKey>,
+    /// The primary cache key, reported in `Cache-Status`
+    pub cache_key: Option<String>,
+    /// Why the request had to be forwarded to the origin, once a cache lookup has decided it cannot be served
+    pub cache_fwd: Option<ForwardReason>,
+    /// The `TieredStorage` tier in which the cached object was found
+    pub cache_tier: Option<CacheTier>,
+    /// The status code of the origin's response
+    pub upstream_status: Option<u16>,
+    /// The routing table entry for the requested host, or `None` if unknown hosts are passed through
+    pub route: Option<&'static Route>,
+    /// The origin pool backend chosen for this request, whose health is updated from the outcome
+    pub backend: Option<SocketAddr>,
+    /// The number of times the request has been sent towards an origin, including retries
+    pub attempts: u32,
+    /// The upstream connection, and whether it was reused from the pool
+    pub upstream_connection: Option<UpstreamConnection>,
+}
@@ -0,0 +1,211 @@
+use crate::{
+    consts::DEFAULT_EGRESS_ALLOW_CIDRS, metrics::proxy_metrics, proxy::trusted_proxies::parse_cidrs,
+    utils::env_var_or_str,
+};
+
+use ipnet::IpNet;
+use pingora_error::{Error, ErrorSource, ErrorType};
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::OnceLock,
+};
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// Destinations in restricted ranges may only be contacted if they fall within one of these networks
+pub struct EgressCfg {
+    pub allow: Vec<IpNet>,
+}
+
+static EGRESS_CFG: OnceLock<EgressCfg> = OnceLock::new();
+pub fn egress_cfg() -> &'static EgressCfg {
+    EGRESS_CFG.get_or_init(|| EgressCfg {
+        allow: parse_cidrs(&env_var_or_str("EGRESS_ALLOW_CIDRS", DEFAULT_EGRESS_ALLOW_CIDRS)),
+    })
+}
+
+impl EgressCfg {
+    pub fn permits(&self, addr: IpAddr) -> bool {
+        let target = embedded_ipv4(addr).map_or(addr, IpAddr::V4);
+        !is_restricted(target) || self.allow.iter().any(|net| net.contains(&target) || net.contains(&addr))
+    }
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// An IPv6 address that reaches an IPv4 destination is checked as that IPv4 address, otherwise ::ffff:127.0.0.1 or
+// 64:ff9b::a9fe:a9fe would slip past the IPv4 rules. This covers IPv4-mapped addresses, the deprecated IPv4-compatible
+// addresses (::a.b.c.d) and the well-known NAT64 prefix 64:ff9b::/96 (RFC 6052)
+fn embedded_ipv4(addr: IpAddr) -> Option<Ipv4Addr> {
+    let IpAddr::V6(v6) = addr else { return None };
+    let segments = v6.segments();
+    let low_32 = || Ipv4Addr::from((u32::from(segments[6]) << 16) | u32::from(segments[7]));
+
+    match segments {
+        [0, 0, 0, 0, 0, 0xffff, ..] => Some(low_32()),
+        // :: and ::1 keep their IPv6 meanings
+        [0, 0, 0, 0, 0, 0, ..] if !v6.is_unspecified() && !v6.is_loopback() => Some(low_32()),
+        [0x64, 0xff9b, 0, 0, 0, 0, ..] => Some(low_32()),
+        _ => None,
+    }
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// Loopback, link-local (which includes cloud metadata services at 169.254.169.254), private, multicast and IPv6 unique
+// local addresses, plus the unspecified and broadcast addresses, which some stacks treat as loopback.
+// The local-use NAT64 prefix 64:ff9b:1::/48 (RFC 8215) is also restricted, as where it embeds the IPv4 address depends
+// on how the translator is configured
+fn is_restricted(addr: IpAddr) -> bool {
+    match addr {
+        IpAddr::V4(v4) => {
+            v4.is_loopback()
+                || v4.is_link_local()
+                || v4.is_private()
+                || v4.is_multicast()
+                || v4.is_broadcast()
+                || v4.is_unspecified()
+        },
+        IpAddr::V6(v6) => {
+            v6.is_loopback()
+                || v6.is_unicast_link_local()
+                || v6.is_unique_local()
+                || v6.is_multicast()
+                || v6.is_unspecified()
+                || v6.segments()[..3] == [0x64, 0xff9b, 1]
+        },
+    }
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+/// Resolve an origin and return the first of its addresses that the egress policy permits
+///
+/// The proxy then connects to this address rather than to the host name, so a second DNS lookup cannot substitute a
+/// restricted address for the one that was checked.
+/// If every address is restricted, the request is refused with `403 Forbidden`
+pub async fn resolve_permitted(host: &str, port: u16) -> pingora_error::Result<SocketAddr> {
+    let addrs: Vec<SocketAddr> = match tokio::net::lookup_host((host, port)).await {
+        Ok(addrs) => addrs.collect(),
+        Err(e) => {
+            let mut err = Error::explain(ErrorType::ConnectNoRoute, format!("Unable to resolve {host}: {e}"));
+            err.esource = ErrorSource::Upstream;
+            return Err(err);
+        },
+    };
+
+    match addrs.iter().find(|addr| egress_cfg().permits(addr.ip())) {
+        Some(addr) => Ok(*addr),
+        None => refuse(host, &addrs),
+    }
+}
+
+/// Check an origin address that is already known, such as a backend of an origin pool
+pub fn ensure_permitted(host: &str, addr: SocketAddr) -> pingora_error::Result<()> {
+    if egress_cfg().permits(addr.ip()) { Ok(()) } else { refuse(host, &[addr]) }
+}
+
+fn refuse<T>(host: &str, addrs: &[SocketAddr]) -> pingora_error::Result<T> {
+    tracing::warn!("Egress policy refused connection to {host} ({addrs:?})");
+    proxy_metrics().egress_rejections.inc();
+    Error::e_explain(
+        ErrorType::HTTPStatus(403),
+        format!("Egress policy does not permit connections to {host}"),
+    )
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cfg(allow: &str) -> EgressCfg {
+        EgressCfg { allow: parse_cidrs(allow) }
+    }
+
+    fn permitted(cfg: &EgressCfg, addr: &str) -> bool {
+        cfg.permits(addr.parse().unwrap())
+    }
+
+    #[test]
+    fn public_addresses_are_permitted() {
+        let cfg = cfg("");
+
+        assert!(permitted(&cfg, "93.184.216.34"));
+        assert!(permitted(&cfg, "2606:2800:220:1:248:1893:25c8:1946"));
+    }
+
+    #[test]
+    fn restricted_ipv4_ranges_are_refused() {
+        let cfg = cfg("");
+
+        for addr in ["127.0.0.1", "10.1.2.3", "172.16.0.1", "192.168.1.1", "169.254.169.254", "224.0.0.1", "0.0.0.0"] {
+            assert!(!permitted(&cfg, addr), "{addr}");
+        }
+        assert!(!permitted(&cfg, "255.255.255.255"));
+    }
+
+    #[test]
+    fn restricted_ipv6_ranges_are_refused() {
+        let cfg = cfg("");
+
+        for addr in ["::1", "::", "fe80::1", "fc00::1", "fd12:3456::1", "ff02::1", "64:ff9b:1::a00:1"] {
+            assert!(!permitted(&cfg, addr), "{addr}");
+        }
+    }
+
+    #[test]
+    fn ipv4_mapped_addresses_are_checked_as_ipv4() {
+        let cfg = cfg("");
+
+        assert_eq!(embedded_ipv4("::ffff:127.0.0.1".parse().unwrap()), Some(Ipv4Addr::LOCALHOST));
+        assert!(!permitted(&cfg, "::ffff:127.0.0.1"));
+        assert!(!permitted(&cfg, "::ffff:169.254.169.254"));
+        assert!(permitted(&cfg, "::ffff:93.184.216.34"));
+    }
+
+    #[test]
+    fn ipv4_compatible_addresses_are_checked_as_ipv4() {
+        let cfg = cfg("");
+
+        assert!(!permitted(&cfg, "::127.0.0.1"));
+        assert!(!permitted(&cfg, "::a9fe:a9fe"));
+        assert!(permitted(&cfg, "::93.184.216.34"));
+
+        // The IPv6 loopback and unspecified addresses are not IPv4-compatible addresses
+        assert_eq!(embedded_ipv4("::1".parse().unwrap()), None);
+        assert_eq!(embedded_ipv4("::".parse().unwrap()), None);
+    }
+
+    #[test]
+    fn nat64_addresses_are_checked_as_ipv4() {
+        let cfg = cfg("");
+
+        assert_eq!(
+            embedded_ipv4("64:ff9b::a9fe:a9fe".parse().unwrap()),
+            Some(Ipv4Addr::new(169, 254, 169, 254))
+        );
+        assert!(!permitted(&cfg, "64:ff9b::a9fe:a9fe"));
+        assert!(!permitted(&cfg, "64:ff9b::10.0.0.1"));
+        assert!(permitted(&cfg, "64:ff9b::93.184.216.34"));
+    }
+
+    #[test]
+    fn allowlist_overrides_restrictions() {
+        let cfg = cfg("10.0.0.0/24, fd00::/8, 169.254.169.254");
+
+        assert!(permitted(&cfg, "10.0.0.5"));
+        assert!(!permitted(&cfg, "10.0.1.5"));
+        assert!(permitted(&cfg, "fd00::1"));
+        assert!(!permitted(&cfg, "fc00::1"));
+        assert!(permitted(&cfg, "169.254.169.254"));
+        assert!(!permitted(&cfg, "169.254.169.253"));
+    }
+
+    #[test]
+    fn allowlist_matches_either_form_of_an_embedded_address() {
+        let v4 = cfg("10.0.0.0/24");
+        assert!(permitted(&v4, "::ffff:10.0.0.5"));
+        assert!(permitted(&v4, "64:ff9b::10.0.0.5"));
+
+        let v6 = cfg("64:ff9b::/96");
+        assert!(permitted(&v6, "64:ff9b::10.0.0.5"));
+        assert!(!permitted(&v6, "::ffff:10.0.0.5"));
+    }
+}
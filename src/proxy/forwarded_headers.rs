@@ -0,0 +1,126 @@
+use crate::{consts::DEFAULT_FORWARDED_HEADERS_MODE, proxy::trusted_proxies::from_trusted_proxy, utils::env_var_or_str};
+
+use pingora::{http::RequestHeader, prelude::Session};
+use std::{net::IpAddr, sync::OnceLock};
+
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+const X_FORWARDED_HOST: &str = "x-forwarded-host";
+const X_FORWARDED_PROTO: &str = "x-forwarded-proto";
+const FORWARDED: &str = "forwarded";
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+/// What happens to forwarding headers that arrive from a trusted proxy.
+/// Headers from any other client are always discarded, as the client could write anything in them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ForwardedMode {
+    /// Keep the trusted proxy's values and add this hop to `X-Forwarded-For` and `Forwarded`
+    Append,
+    /// Discard all incoming values, so the origin only sees what this node observed
+    Overwrite,
+}
+
+pub struct ForwardedHeadersCfg {
+    pub mode: ForwardedMode,
+}
+
+static FORWARDED_HEADERS_CFG: OnceLock<ForwardedHeadersCfg> = OnceLock::new();
+pub fn forwarded_headers_cfg() -> &'static ForwardedHeadersCfg {
+    FORWARDED_HEADERS_CFG.get_or_init(|| {
+        let mode = env_var_or_str("FORWARDED_HEADERS_MODE", DEFAULT_FORWARDED_HEADERS_MODE);
+
+        ForwardedHeadersCfg {
+            mode: match mode.trim().to_ascii_lowercase().as_str() {
+                "append" => ForwardedMode::Append,
+                "overwrite" => ForwardedMode::Overwrite,
+                other => {
+                    tracing::warn!("Unknown FORWARDED_HEADERS_MODE \"{other}\", using \"append\"");
+                    ForwardedMode::Append
+                },
+            },
+        }
+    })
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+/// Tell the origin who the client is, which host it asked for and which scheme it used
+///
+/// `host` is the authority requested by the client (before any `host_rewrite`) and `proto` is the request's scheme
+pub fn add_forwarding_headers(
+    session: &Session,
+    upstream_request: &mut RequestHeader,
+    host: &str,
+    proto: &str,
+) -> pingora_error::Result<()> {
+    let keep_incoming = forwarded_headers_cfg().mode == ForwardedMode::Append && from_trusted_proxy(session);
+    let client_ip = session
+        .client_addr()
+        .and_then(|addr| addr.as_inet())
+        .map(|inet| inet.ip().to_canonical());
+
+    let incoming_for = joined_values(upstream_request, X_FORWARDED_FOR).filter(|_| keep_incoming);
+    let incoming_host = joined_values(upstream_request, X_FORWARDED_HOST).filter(|_| keep_incoming);
+    let incoming_forwarded = joined_values(upstream_request, FORWARDED).filter(|_| keep_incoming);
+
+    for name in [X_FORWARDED_FOR, X_FORWARDED_HOST, X_FORWARDED_PROTO, FORWARDED] {
+        upstream_request.remove_header(name);
+    }
+
+    let client = client_ip.map_or_else(|| "unknown".to_string(), |ip| ip.to_string());
+    upstream_request.insert_header(X_FORWARDED_FOR, append(incoming_for, &client))?;
+
+    // The original host and scheme are those seen by the first proxy, so a trusted proxy's values take precedence.
+    // A forwarded scheme has already been honoured when the scheme of a trusted request was determined
+    upstream_request.insert_header(X_FORWARDED_HOST, incoming_host.as_deref().unwrap_or(host))?;
+    upstream_request.insert_header(X_FORWARDED_PROTO, proto)?;
+
+    let element = format!(
+        "for={};host={};proto={proto}",
+        forwarded_node(client_ip),
+        forwarded_value(host)
+    );
+    upstream_request.insert_header(FORWARDED, append(incoming_forwarded, &element))?;
+
+    Ok(())
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// A header may be split across several lines, which are equivalent to one comma-separated list
+fn joined_values(req: &RequestHeader, name: &str) -> Option<String> {
+    let values: Vec<&str> = req
+        .headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .collect();
+
+    if values.is_empty() { None } else { Some(values.join(", ")) }
+}
+
+fn append(incoming: Option<String>, value: &str) -> String {
+    match incoming {
+        Some(list) => format!("{list}, {value}"),
+        None => value.to_string(),
+    }
+}
+
+// RFC 7239 §6: an IPv6 node is bracketed, which requires the value to be quoted
+fn forwarded_node(ip: Option<IpAddr>) -> String {
+    match ip {
+        Some(IpAddr::V6(v6)) => format!("\"[{v6}]\""),
+        Some(ip) => ip.to_string(),
+        None => "unknown".to_string(),
+    }
+}
+
+// RFC 7239 §4: a value that is not a token, such as a host with a port, must be a quoted string
+fn forwarded_value(value: &str) -> String {
+    let is_tchar = |c: char| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c);
+
+    if !value.is_empty() && value.chars().all(is_tchar) {
+        value.to_string()
+    } else {
+        format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
@@ -0,0 +1,138 @@
+use crate::consts::{CACHE_STATUS_NAME, PURGE_SECRET_HEADER};
+
+use bytes::Bytes;
+use pingora::{
+    http::{Method, RequestHeader, ResponseHeader, StatusCode, Version},
+    prelude::Session,
+};
+use std::{
+    collections::HashSet,
+    net::{IpAddr, SocketAddr},
+    sync::OnceLock,
+};
+
+// Request headers that are never echoed back in the response to a TRACE
+const TRACE_REDACTED_HEADERS: [&str; 4] = ["authorization", "proxy-authorization", "cookie", PURGE_SECRET_HEADER];
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+/// The name by which this node identifies itself in `Via` headers, which defaults to the machine's host name.
+/// Every node in a cache hierarchy must have a different identifier
+static NODE_ID: OnceLock<String> = OnceLock::new();
+pub fn node_id() -> &'static str {
+    NODE_ID.get_or_init(|| {
+        std::env::var("EDGE_NODE_ID").ok().filter(|id| !id.is_empty()).unwrap_or_else(|| {
+            hostname::get()
+                .ok()
+                .and_then(|name| name.into_string().ok())
+                .unwrap_or_else(|| CACHE_STATUS_NAME.to_string())
+        })
+    })
+}
+
+/// The `Via` entry this node adds to a message received with the given HTTP version (RFC 9110 §7.6.3)
+pub fn via_entry(version: Version) -> String {
+    let protocol = match version {
+        Version::HTTP_09 => "0.9",
+        Version::HTTP_10 => "1.0",
+        Version::HTTP_2 => "2",
+        Version::HTTP_3 => "3",
+        _ => "1.1",
+    };
+
+    format!("{protocol} {}", node_id())
+}
+
+/// Whether this node already appears as a recipient in the request's `Via` headers, in which case the request has
+/// been forwarded around a loop
+pub fn via_contains_node(req: &RequestHeader) -> bool {
+    req.headers
+        .get_all("via")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|entry| entry.split_whitespace().nth(1))
+        .any(|received_by| received_by.eq_ignore_ascii_case(node_id()))
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+/// The value of `Max-Forwards`, which RFC 9110 §7.6.2 only defines for `TRACE` and `OPTIONS`
+pub fn max_forwards(req: &RequestHeader) -> Option<u32> {
+    if req.method != Method::TRACE && req.method != Method::OPTIONS {
+        return None;
+    }
+
+    req.headers
+        .get("max-forwards")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse().ok())
+}
+
+/// Answer a `TRACE` or `OPTIONS` request whose `Max-Forwards` has reached zero, as this node is its final recipient.
+/// A `TRACE` is answered with the request it received, less any credentials
+pub async fn respond_as_final_recipient(session: &mut Session) -> pingora_error::Result<()> {
+    let req = session.req_header();
+    let body = if req.method == Method::TRACE {
+        let mut echo = format!("{} {} {:?}\r\n", req.method, req.uri, req.version);
+        for (name, value) in req.headers.iter() {
+            if !TRACE_REDACTED_HEADERS.contains(&name.as_str()) {
+                echo.push_str(&format!("{name}: {}\r\n", String::from_utf8_lossy(value.as_bytes())));
+            }
+        }
+        Some(Bytes::from(echo))
+    } else {
+        None
+    };
+
+    let mut resp = ResponseHeader::build(StatusCode::OK, Some(3))?;
+    resp.insert_header("content-length", body.as_ref().map_or(0, Bytes::len))?;
+    match body {
+        Some(_) => resp.insert_header("content-type", "message/http")?,
+        None => resp.insert_header("allow", "GET, HEAD, POST, PUT, DELETE, OPTIONS, TRACE")?,
+    }
+
+    let end_of_stream = body.is_none();
+    session.write_response_header(Box::new(resp), end_of_stream).await?;
+    if body.is_some() {
+        session.write_response_body(body, true).await?;
+    }
+
+    Ok(())
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+/// The addresses on which this node accepts requests
+///
+/// A listener bound to the unspecified address accepts connections on every interface, so the addresses of all
+/// interfaces are collected when the proxy starts.
+/// Interfaces that appear later are not recognised
+#[derive(Debug)]
+pub struct SelfAddresses {
+    listeners: Vec<SocketAddr>,
+    interfaces: HashSet<IpAddr>,
+}
+
+impl SelfAddresses {
+    pub fn new(listeners: Vec<SocketAddr>) -> Self {
+        let interfaces = match local_ip_address::list_afinet_netifas() {
+            Ok(netifas) => netifas.into_iter().map(|(_, ip)| ip).collect(),
+            Err(e) => {
+                tracing::warn!("Unable to list network interfaces: {e}");
+                HashSet::new()
+            },
+        };
+
+        Self { listeners, interfaces }
+    }
+
+    /// Whether a connection to this address would arrive back at one of this node's listeners
+    pub fn contains(&self, addr: SocketAddr) -> bool {
+        let ip = addr.ip().to_canonical();
+
+        self.listeners.iter().any(|listener| {
+            listener.port() == addr.port()
+                && (listener.ip().to_canonical() == ip
+                    || (listener.ip().is_unspecified()
+                        && (ip.is_loopback() || ip.is_unspecified() || self.interfaces.contains(&ip))))
+        })
+    }
+}
@@ -0,0 +1,494 @@
+use crate::{
+    consts::{DEFAULT_STALE_IF_ERROR_SECS, DEFAULT_STALE_WHILE_REVALIDATE_SECS, DEFAULT_STATUS_TTLS, ONE_HOUR},
+    utils::{env_var_or_num, env_var_or_str},
+};
+
+use httpdate::HttpDate;
+use pingora::http::{ResponseHeader, StatusCode};
+use pingora_cache::{
+    cache_control::{CacheControl, InterpretCacheControl},
+    CacheMeta,
+};
+use std::{
+    collections::HashMap,
+    sync::OnceLock,
+    time::{Duration, SystemTime},
+};
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// Values used when the origin supplies no explicit expiration time or stale-serving directives
+pub struct FreshnessCfg {
+    pub default_ttl: Duration,
+    pub status_ttls: StatusTtls,
+    pub default_stale_while_revalidate: u32,
+    pub default_stale_if_error: u32,
+}
+
+impl FreshnessCfg {
+    /// The TTL used for a response with the given status when the origin supplies no explicit expiration time
+    pub fn default_ttl_for(&self, status: StatusCode) -> Duration {
+        self.status_ttls.get(status).unwrap_or(self.default_ttl)
+    }
+}
+
+static FRESHNESS_CFG: OnceLock<FreshnessCfg> = OnceLock::new();
+pub fn freshness_cfg() -> &'static FreshnessCfg {
+    FRESHNESS_CFG.get_or_init(|| FreshnessCfg {
+        default_ttl: Duration::from_secs(env_var_or_num("CACHE_DEFAULT_TTL_SECS", ONE_HOUR.as_secs())),
+        status_ttls: StatusTtls::parse(&env_var_or_str("CACHE_STATUS_TTLS", DEFAULT_STATUS_TTLS)),
+        default_stale_while_revalidate: env_var_or_num(
+            "CACHE_STALE_WHILE_REVALIDATE_SECS",
+            DEFAULT_STALE_WHILE_REVALIDATE_SECS,
+        ),
+        default_stale_if_error: env_var_or_num("CACHE_STALE_IF_ERROR_SECS", DEFAULT_STALE_IF_ERROR_SECS),
+    })
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+/// Default TTLs for particular status codes or status classes
+///
+/// Parsed from a comma separated list of `status=seconds` pairs, where `status` is either an exact code such as `404`
+/// or a class such as `4xx`.
+/// An exact code takes precedence over its class. Malformed entries are ignored with a warning.
+#[derive(Debug, Default)]
+pub struct StatusTtls {
+    exact: HashMap<u16, Duration>,
+    class: [Option<Duration>; 6],
+}
+
+impl StatusTtls {
+    pub fn parse(spec: &str) -> Self {
+        let mut ttls = Self::default();
+
+        for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let parsed = entry.split_once('=').and_then(|(status, secs)| {
+                let ttl = Duration::from_secs(secs.trim().parse::<u64>().ok()?);
+                let status = status.trim().to_ascii_lowercase();
+
+                match status.strip_suffix("xx") {
+                    Some(class) => {
+                        let class = class.parse::<usize>().ok().filter(|c| (1..=5).contains(c))?;
+                        ttls.class[class] = Some(ttl);
+                    },
+                    None => {
+                        let code = status.parse::<u16>().ok().filter(|c| (100..=599).contains(c))?;
+                        ttls.exact.insert(code, ttl);
+                    },
+                }
+
+                Some(())
+            });
+
+            if parsed.is_none() {
+                tracing::warn!("Ignoring malformed status TTL \"{entry}\"");
+            }
+        }
+
+        ttls
+    }
+
+    pub fn get(&self, status: StatusCode) -> Option<Duration> {
+        let code = status.as_u16();
+        self.exact
+            .get(&code)
+            .copied()
+            .or_else(|| self.class.get((code / 100) as usize).copied().flatten())
+    }
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+/// Freshness of an origin response as seen by a shared cache (RFC 9111 §4.2)
+///
+/// The freshness lifetime is taken from the first of the following that is present:
+///
+///   1. `Cache-Control: no-cache` (lifetime of zero; the response must always be revalidated)
+///   2. `Cache-Control: s-maxage`
+///   3. `Cache-Control: max-age`
+///   4. `Expires` minus `Date`
+///   5. The configured default TTL for the response's status, falling back to the overall default TTL
+///
+/// The age is the corrected initial age of the response: the larger of the origin's `Age` header and the difference
+/// between the time we received the response and its `Date` header.
+///
+/// The stale windows (RFC 5861) are taken from `stale-while-revalidate` and `stale-if-error`, or from the configured
+/// defaults.
+/// `must-revalidate`, `proxy-revalidate` and `s-maxage` forbid serving stale content, so these always give windows of
+/// zero.
+#[derive(Debug, Clone, Copy)]
+pub struct Freshness {
+    pub lifetime: Duration,
+    pub age: Duration,
+    pub stale_while_revalidate: u32,
+    pub stale_if_error: u32,
+}
+
+impl Freshness {
+    pub fn from_response(
+        resp: &ResponseHeader,
+        cc: Option<&CacheControl>,
+        response_time: SystemTime,
+        cfg: &FreshnessCfg,
+    ) -> Self {
+        let date = header_date(resp, "date");
+
+        let lifetime = cc
+            .and_then(lifetime_from_cache_control)
+            .or_else(|| lifetime_from_expires(resp, date.unwrap_or(response_time)))
+            .unwrap_or_else(|| cfg.default_ttl_for(resp.status));
+
+        // RFC 9111 §4.2.3: apparent_age = max(0, response_time - date_value)
+        let apparent_age = date.and_then(|d| response_time.duration_since(d).ok()).unwrap_or_default();
+        let age = apparent_age.max(age_header(resp).unwrap_or_default());
+
+        let stale_while_revalidate = cc
+            .and_then(|cc| cc.serve_stale_while_revalidate_duration())
+            .map_or(cfg.default_stale_while_revalidate, duration_to_secs);
+        let stale_if_error = cc
+            .and_then(|cc| cc.serve_stale_if_error_duration())
+            .map_or(cfg.default_stale_if_error, duration_to_secs);
+
+        Self {
+            lifetime,
+            age,
+            stale_while_revalidate,
+            stale_if_error,
+        }
+    }
+
+    /// How much longer the response remains fresh, given its age when we received it
+    pub fn remaining(&self) -> Duration {
+        self.lifetime.saturating_sub(self.age)
+    }
+
+    /// The time until which a response received at `response_time` is fresh
+    pub fn fresh_until(&self, response_time: SystemTime) -> SystemTime {
+        let remaining = self.remaining();
+
+        if remaining.is_zero() {
+            // CacheMeta treats fresh_until == now as fresh, so push it into the past
+            response_time.checked_sub(Duration::from_secs(1)).unwrap_or(response_time)
+        } else {
+            response_time.checked_add(remaining).unwrap_or(response_time)
+        }
+    }
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// s-maxage is aimed specifically at shared caches, so it takes precedence over max-age
+// Malformed values are ignored so that the next source of freshness information is used instead
+fn lifetime_from_cache_control(cc: &CacheControl) -> Option<Duration> {
+    if cc.no_cache() {
+        return Some(Duration::ZERO);
+    }
+
+    cc.s_maxage()
+        .ok()
+        .flatten()
+        .or_else(|| cc.max_age().ok().flatten())
+        .map(|secs| Duration::from_secs(secs as u64))
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// RFC 9111 §5.3: an invalid Expires value (including multiple Expires headers) represents a time in the past
+fn lifetime_from_expires(resp: &ResponseHeader, date: SystemTime) -> Option<Duration> {
+    let mut values = resp.headers.get_all("expires").iter();
+    let first = values.next()?;
+
+    if values.next().is_some() {
+        return Some(Duration::ZERO);
+    }
+
+    let expires = first
+        .to_str()
+        .ok()
+        .and_then(|s| s.trim().parse::<HttpDate>().ok())
+        .map(SystemTime::from);
+
+    Some(expires.and_then(|e| e.duration_since(date).ok()).unwrap_or_default())
+}
+
+fn duration_to_secs(d: Duration) -> u32 {
+    d.as_secs().try_into().unwrap_or(u32::MAX)
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+/// The current age of a cached response (RFC 9111 §4.2.3)
+///
+/// This is the age the response already had when it was stored, plus the time it has been resident in the cache.
+/// The stored `Date` header is refreshed on every successful revalidation, so the resident time runs from the later of
+/// `CacheMeta::created` and `Date`
+pub fn current_age(meta: &CacheMeta, now: SystemTime) -> Duration {
+    let resp = meta.response_header();
+    let stored_at = header_date(resp, "date").map_or(meta.created(), |d| d.max(meta.created()));
+    let initial_age = header_date(resp, "date")
+        .and_then(|d| meta.created().duration_since(d).ok())
+        .unwrap_or_default()
+        .max(age_header(resp).unwrap_or_default());
+
+    initial_age + now.duration_since(stored_at).unwrap_or_default()
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+pub fn header_date(resp: &ResponseHeader, name: &str) -> Option<SystemTime> {
+    resp.headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|s| s.trim().parse::<HttpDate>().ok())
+        .map(SystemTime::from)
+}
+
+// The Age header is a non-negative number of seconds
+pub fn age_header(resp: &ResponseHeader) -> Option<Duration> {
+    resp.headers
+        .get("age")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|s| s.trim().parse::<u64>().ok())
+        .map(Duration::from_secs)
+}
+
+// Does the response carry an explicit expiration time, as opposed to relying on a heuristic or default TTL?
+pub fn has_explicit_expiry(resp: &ResponseHeader, cc: Option<&CacheControl>) -> bool {
+    cc.is_some_and(|cc| cc.has_key("s-maxage") || cc.has_key("max-age")) || resp.headers.contains_key("expires")
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    // Whole seconds, so that times survive the round trip through an HTTP date
+    fn now() -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+    }
+
+    fn http_date(t: SystemTime) -> String {
+        HttpDate::from(t).to_string()
+    }
+
+    fn cfg() -> FreshnessCfg {
+        FreshnessCfg {
+            default_ttl: ONE_HOUR,
+            status_ttls: StatusTtls::parse("404=30"),
+            default_stale_while_revalidate: 0,
+            default_stale_if_error: 0,
+        }
+    }
+
+    fn response(status: u16, headers: &[(&'static str, String)]) -> ResponseHeader {
+        let mut resp = ResponseHeader::build(status, None).unwrap();
+        for (name, value) in headers {
+            resp.append_header(*name, value.as_str()).unwrap();
+        }
+        resp
+    }
+
+    fn freshness_with(resp: &ResponseHeader, cfg: &FreshnessCfg) -> Freshness {
+        let cc = CacheControl::from_resp_headers(resp);
+        Freshness::from_response(resp, cc.as_ref(), now(), cfg)
+    }
+
+    fn freshness(resp: &ResponseHeader) -> Freshness {
+        freshness_with(resp, &cfg())
+    }
+
+    fn lifetime(headers: &[(&'static str, String)]) -> Duration {
+        freshness(&response(200, headers)).lifetime
+    }
+
+    #[test]
+    fn s_maxage_takes_precedence_over_max_age_and_expires() {
+        let expires = ("expires", http_date(now() + 10 * MINUTE));
+        let date = ("date", http_date(now()));
+
+        assert_eq!(
+            lifetime(&[("cache-control", "max-age=60, s-maxage=120".into()), date.clone(), expires.clone()]),
+            Duration::from_secs(120)
+        );
+        assert_eq!(
+            lifetime(&[("cache-control", "max-age=60".into()), date.clone(), expires.clone()]),
+            Duration::from_secs(60)
+        );
+        assert_eq!(lifetime(&[date, expires]), 10 * MINUTE);
+    }
+
+    #[test]
+    fn no_cache_gives_a_lifetime_of_zero() {
+        assert_eq!(lifetime(&[("cache-control", "no-cache, max-age=60".into())]), Duration::ZERO);
+    }
+
+    #[test]
+    fn malformed_max_age_falls_back_to_expires() {
+        let headers = [
+            ("cache-control", "max-age=soon".into()),
+            ("date", http_date(now())),
+            ("expires", http_date(now() + 5 * MINUTE)),
+        ];
+
+        assert_eq!(lifetime(&headers), 5 * MINUTE);
+    }
+
+    #[test]
+    fn expires_is_measured_from_date_or_else_the_response_time() {
+        // The origin's clock is ahead of ours, which must not change the lifetime it intended
+        let origin_now = now() + 30 * MINUTE;
+        assert_eq!(
+            lifetime(&[("date", http_date(origin_now)), ("expires", http_date(origin_now + 2 * MINUTE))]),
+            2 * MINUTE
+        );
+
+        assert_eq!(lifetime(&[("expires", http_date(now() + 3 * MINUTE))]), 3 * MINUTE);
+    }
+
+    #[test]
+    fn invalid_or_past_expires_means_already_expired() {
+        assert_eq!(lifetime(&[("expires", "0".into())]), Duration::ZERO);
+        assert_eq!(lifetime(&[("expires", http_date(now() - MINUTE))]), Duration::ZERO);
+        assert_eq!(
+            lifetime(&[("expires", http_date(now() + MINUTE)), ("expires", http_date(now() + MINUTE))]),
+            Duration::ZERO
+        );
+    }
+
+    #[test]
+    fn heuristic_freshness_uses_the_default_ttl_for_the_status() {
+        assert_eq!(lifetime(&[]), ONE_HOUR);
+        assert_eq!(freshness(&response(404, &[])).lifetime, Duration::from_secs(30));
+        assert_eq!(freshness(&response(410, &[])).lifetime, ONE_HOUR);
+    }
+
+    #[test]
+    fn age_is_the_larger_of_the_age_header_and_apparent_age() {
+        let resp = response(200, &[("date", http_date(now() - 2 * MINUTE)), ("age", "30".into())]);
+        assert_eq!(freshness(&resp).age, 2 * MINUTE);
+
+        let resp = response(200, &[("date", http_date(now() - 2 * MINUTE)), ("age", "300".into())]);
+        assert_eq!(freshness(&resp).age, 5 * MINUTE);
+
+        // A Date in the future gives no apparent age
+        let resp = response(200, &[("date", http_date(now() + 2 * MINUTE))]);
+        assert_eq!(freshness(&resp).age, Duration::ZERO);
+
+        let resp = response(200, &[("age", "-5".into())]);
+        assert_eq!(freshness(&resp).age, Duration::ZERO);
+    }
+
+    #[test]
+    fn age_is_deducted_from_the_lifetime() {
+        let resp = response(200, &[("cache-control", "max-age=600".into()), ("age", "120".into())]);
+        let freshness = freshness(&resp);
+
+        assert_eq!(freshness.remaining(), 8 * MINUTE);
+        assert_eq!(freshness.fresh_until(now()), now() + 8 * MINUTE);
+    }
+
+    #[test]
+    fn a_response_older_than_its_lifetime_is_already_stale() {
+        let resp = response(200, &[("cache-control", "max-age=60".into()), ("age", "120".into())]);
+        let freshness = freshness(&resp);
+
+        assert_eq!(freshness.remaining(), Duration::ZERO);
+        assert!(freshness.fresh_until(now()) < now());
+    }
+
+    #[test]
+    fn stale_windows_come_from_cache_control_or_the_defaults() {
+        let value = "max-age=60, stale-while-revalidate=30, stale-if-error=90";
+        let freshness = freshness(&response(200, &[("cache-control", value.into())]));
+        assert_eq!((freshness.stale_while_revalidate, freshness.stale_if_error), (30, 90));
+
+        let cfg = FreshnessCfg {
+            default_stale_while_revalidate: 5,
+            default_stale_if_error: 15,
+            ..cfg()
+        };
+        let freshness = freshness_with(&response(200, &[("cache-control", "max-age=60".into())]), &cfg);
+        assert_eq!((freshness.stale_while_revalidate, freshness.stale_if_error), (5, 15));
+
+        for directive in ["must-revalidate", "proxy-revalidate", "s-maxage=60"] {
+            let value = format!("max-age=60, {directive}, stale-while-revalidate=30, stale-if-error=90");
+            let freshness = freshness_with(&response(200, &[("cache-control", value)]), &cfg);
+            assert_eq!((freshness.stale_while_revalidate, freshness.stale_if_error), (0, 0), "{directive}");
+        }
+    }
+
+    #[test]
+    fn current_age_adds_resident_time_to_the_initial_age() {
+        let stored_at = now() - 10 * MINUTE;
+        let resp = response(200, &[("date", http_date(stored_at - MINUTE)), ("age", "30".into())]);
+        let meta = CacheMeta::new(now(), stored_at, 0, 0, resp);
+
+        // One minute old when stored, plus ten minutes in the cache
+        assert_eq!(current_age(&meta, now()), 11 * MINUTE);
+    }
+
+    #[test]
+    fn current_age_runs_from_a_refreshed_date() {
+        // The stored Date is replaced when the entry is revalidated, after the object was first created
+        let created = now() - 10 * MINUTE;
+        let resp = response(200, &[("date", http_date(now() - 2 * MINUTE))]);
+        let meta = CacheMeta::new(now(), created, 0, 0, resp);
+
+        assert_eq!(current_age(&meta, now()), 2 * MINUTE);
+    }
+
+    #[test]
+    fn current_age_without_date_uses_the_age_header() {
+        let resp = response(200, &[("age", "45".into())]);
+        let meta = CacheMeta::new(now(), now() - MINUTE, 0, 0, resp);
+
+        assert_eq!(current_age(&meta, now()), Duration::from_secs(105));
+    }
+
+    fn ttl(ttls: &StatusTtls, status: u16) -> Option<Duration> {
+        ttls.get(StatusCode::from_u16(status).unwrap())
+    }
+
+    #[test]
+    fn exact_status_takes_precedence_over_its_class() {
+        for spec in ["404=30, 4xx=60", "4xx=60, 404=30"] {
+            let ttls = StatusTtls::parse(spec);
+
+            assert_eq!(ttl(&ttls, 404), Some(Duration::from_secs(30)), "{spec}");
+            assert_eq!(ttl(&ttls, 410), Some(MINUTE), "{spec}");
+            assert_eq!(ttl(&ttls, 500), None, "{spec}");
+        }
+    }
+
+    #[test]
+    fn status_ttls_ignore_case_and_whitespace() {
+        let ttls = StatusTtls::parse(" 3XX = 600 ,, 200=0 ");
+
+        assert_eq!(ttl(&ttls, 301), Some(10 * MINUTE));
+        assert_eq!(ttl(&ttls, 200), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn later_entries_replace_earlier_ones() {
+        let ttls = StatusTtls::parse("404=30, 404=90, 5xx=1, 5xx=2");
+
+        assert_eq!(ttl(&ttls, 404), Some(Duration::from_secs(90)));
+        assert_eq!(ttl(&ttls, 503), Some(Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn malformed_status_ttls_are_ignored() {
+        let spec = "404, =30, 404=, 404=-1, 404=soon, 6xx=30, 0xx=30, xx=30, 4x=30, 99=30, 600=30, abc=30, 410=45";
+        let ttls = StatusTtls::parse(spec);
+
+        assert_eq!(ttl(&ttls, 404), None);
+        assert_eq!(ttl(&ttls, 410), Some(Duration::from_secs(45)));
+        assert_eq!(ttls.exact.len(), 1);
+        assert!(ttls.class.iter().all(Option::is_none));
+    }
+
+    #[test]
+    fn empty_spec_sets_no_status_ttls() {
+        let ttls = StatusTtls::parse("");
+
+        assert_eq!(ttl(&ttls, 200), None);
+        assert_eq!(cfg().default_ttl_for(StatusCode::OK), ONE_HOUR);
+    }
+}
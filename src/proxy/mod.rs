@@ -0,0 +1,794 @@
+mod admission;
+pub(crate) mod cache_key;
+mod cache_lock;
+mod cache_status;
+mod cacheability;
+mod client_directives;
+mod context;
+mod egress;
+mod forwarded_headers;
+mod forwarding;
+mod freshness;
+pub(crate) mod origin_pool;
+mod peer_options;
+pub(crate) mod purge;
+mod revalidation;
+pub(crate) mod routing;
+mod surrogate_control;
+pub(crate) mod tenant;
+mod trusted_proxies;
+mod vary;
+
+use crate::{
+    config::edge_config,
+    consts::{DEFAULT_PORT_HTTP, DEFAULT_PORT_HTTPS, HTTP, HTTPS},
+    disk_cache::{disk_cache, eviction_manager, soft_purge::PurgeMode, tags::TAG_HEADERS},
+    logger::{impl_trace, trace_fn_exit, trace_fn_exit_with_err, Trace},
+    metrics::proxy_metrics,
+    proxy::{
+        admission::admission_filter,
+        cache_key::normalize_path_and_query,
+        cache_lock::{cache_lock, cache_lock_overrides},
+        cache_status::{CacheStatus, ForwardReason},
+        cacheability::{cacheability_cfg, cacheable_method, invalidates_stored, shared_cacheability},
+        client_directives::{client_directives_cfg, ClientDirectives},
+        context::EdgeCtx,
+        egress::{ensure_permitted, resolve_permitted},
+        forwarded_headers::add_forwarding_headers,
+        forwarding::{max_forwards, respond_as_final_recipient, via_contains_node, via_entry, SelfAddresses},
+        freshness::{current_age, freshness_cfg, Freshness},
+        peer_options::{count_connection_use, RetryCfg, UpstreamConnection},
+        purge::{is_purge_request, purge_cfg, purge_mode},
+        revalidation::refresh_stored_header,
+        surrogate_control::{edge_cache_control, EDGE_CONTROL_HEADERS},
+        tenant::resolve_tenant,
+        trusted_proxies::from_trusted_proxy,
+        vary::{normalize_request, variance_key},
+    },
+    tiered::{tiered_cache, CacheTier},
+    utils::{parse_host_authority, scheme_from_hdr},
+};
+
+use async_trait::async_trait;
+use httpdate::HttpDate;
+use pingora::{
+    http::{RequestHeader, ResponseHeader, StatusCode, Version},
+    prelude::{ProxyHttp, Session},
+    proxy::PurgeStatus,
+};
+use pingora_cache::{
+    key::{CacheHashKey, HashBinary},
+    storage::HandleHit,
+    CacheKey, CacheMeta, CachePhase, ForcedInvalidationKind, NoCacheReason, RespCacheable,
+};
+use pingora_core::{prelude::HttpPeer, protocols::Digest};
+use pingora_error::{Error, ErrorSource, ErrorType};
+use std::{borrow::Cow, net::SocketAddr, time::SystemTime};
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+pub struct EdgeCdnProxy {
+    self_addresses: SelfAddresses,
+    listen_https: u16,
+}
+
+impl_trace!(EdgeCdnProxy);
+
+impl EdgeCdnProxy {
+    pub fn new(http_listener: SocketAddr, https_listener: SocketAddr) -> Self {
+        <Self as Trace>::fn_enter_exit("new");
+
+        Self {
+            self_addresses: SelfAddresses::new(vec![http_listener, https_listener]),
+            listen_https: https_listener.port(),
+        }
+    }
+
+    // The scheme is taken from the listener on which the request arrived.
+    // A forwarded scheme header is only honoured when the connection comes from a trusted proxy; otherwise, any client
+    // could claim to have used https and so read or poison the https cache entries
+    fn request_scheme(&self, session: &Session) -> &'static str {
+        let listener_https = session
+            .server_addr()
+            .and_then(|sa| sa.as_inet().map(|inet| inet.port()))
+            .map(|p| self.listen_https.eq(&p))
+            .unwrap_or(false);
+        let hdr_scheme = if from_trusted_proxy(session) { scheme_from_hdr(session) } else { None };
+
+        match hdr_scheme {
+            Some(s) if s.eq_ignore_ascii_case(HTTPS) => HTTPS,
+            Some(_) => HTTP,
+            None if listener_https => HTTPS,
+            None => HTTP,
+        }
+    }
+
+    // Expire the object the request's cache key refers to, keeping its body so that it can still be served stale
+    async fn soft_purge(&self, session: &mut Session, ctx: &mut EdgeCtx) -> pingora_error::Result<()> {
+        let key = self.cache_key_callback(session, ctx)?;
+        let (status, result) = if tiered_cache().soft_purge(&key.to_compact()).await {
+            (StatusCode::OK, "expired")
+        } else {
+            (StatusCode::NOT_FOUND, "not_found")
+        };
+
+        tracing::info!(
+            "PURGE (soft) {} from {}: {result}",
+            ctx.cache_key.as_deref().unwrap_or_default(),
+            session.client_addr().map(|addr| addr.to_string()).unwrap_or_default()
+        );
+        proxy_metrics().purge_requests.with_label_values(&[result]).inc();
+        session.respond_error(status.as_u16()).await
+    }
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+#[async_trait]
+impl ProxyHttp for EdgeCdnProxy {
+    type CTX = EdgeCtx;
+
+    // - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+    fn new_ctx(&self) -> Self::CTX {
+        EdgeCtx::default()
+    }
+
+    // - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+    // Reject requests for hosts that have no route before the cache is consulted
+    async fn request_filter(&self, session: &mut Session, ctx: &mut Self::CTX) -> pingora_error::Result<bool> {
+        let fn_name = "request_filter";
+        <Self as Trace>::fn_enter(fn_name);
+
+        // A request that has already passed through this node has been forwarded around a loop
+        if via_contains_node(session.req_header()) {
+            tracing::warn!("Forwarding loop detected: {}", session.request_summary());
+            proxy_metrics().forwarding_loops.inc();
+            session.respond_error(StatusCode::LOOP_DETECTED.as_u16()).await?;
+            trace_fn_exit(fn_name, "rejected forwarding loop", false);
+            return Ok(true);
+        }
+
+        let routing = &edge_config().routing;
+        let host_hdr = session
+            .req_header()
+            .headers
+            .get("Host")
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or_default();
+        let host_lc = parse_host_authority(host_hdr)
+            .map(|(host_only, _)| host_only.to_ascii_lowercase())
+            .unwrap_or_default();
+
+        ctx.route = routing.route_for(&host_lc);
+
+        if ctx.route.is_none()
+            && let Some(status) = routing.unknown_host.reject_status()
+        {
+            tracing::debug!("     no route for host \"{host_lc}\"");
+            proxy_metrics().unknown_host_rejections.inc();
+            session.respond_error(status.as_u16()).await?;
+            trace_fn_exit(fn_name, &format!("rejected with {status}"), false);
+            return Ok(true);
+        }
+
+        // Only trusted sources may invalidate cached objects
+        if is_purge_request(session.req_header()) {
+            if !purge_cfg().permits(session) {
+                tracing::warn!("PURGE refused: {}", session.request_summary());
+                proxy_metrics().purge_requests.with_label_values(&["refused"]).inc();
+                session.respond_error(StatusCode::FORBIDDEN.as_u16()).await?;
+                trace_fn_exit(fn_name, "PURGE refused", false);
+                return Ok(true);
+            }
+
+            // A hard purge is left to Pingora, which calls purge_response_filter() once the object has been deleted
+            match purge_mode(session.req_header()) {
+                Some(PurgeMode::Hard) => {},
+                Some(PurgeMode::Soft) => {
+                    self.soft_purge(session, ctx).await?;
+                    trace_fn_exit(fn_name, "soft PURGE", false);
+                    return Ok(true);
+                },
+                None => {
+                    proxy_metrics().purge_requests.with_label_values(&["refused"]).inc();
+                    session.respond_error(StatusCode::BAD_REQUEST.as_u16()).await?;
+                    trace_fn_exit(fn_name, "PURGE with unknown mode refused", false);
+                    return Ok(true);
+                },
+            }
+        }
+
+        // RFC 9110 §7.6.2: a TRACE or OPTIONS request that may not be forwarded any further is answered here
+        if max_forwards(session.req_header()) == Some(0) {
+            respond_as_final_recipient(session).await?;
+            trace_fn_exit(fn_name, "answered as final recipient", false);
+            return Ok(true);
+        }
+
+        <Self as Trace>::fn_exit(fn_name);
+        Ok(false)
+    }
+
+    // - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+    async fn upstream_peer(&self, session: &mut Session, ctx: &mut Self::CTX) -> pingora_error::Result<Box<HttpPeer>> {
+        let fn_name = "upstream_peer";
+        <Self as Trace>::fn_enter(fn_name);
+
+        // Retrieve Host header
+        let host_hdr = match session.req_header().headers.get("Host").and_then(|h| h.to_str().ok()) {
+            Some(h) => h.trim(),
+            None => {
+                let err_msg = "Host header missing from request";
+                return trace_fn_exit_with_err(fn_name, err_msg, Some(ErrorType::ConnectError), false);
+            },
+        };
+
+        let (host_only, port_from_host) = match parse_host_authority(host_hdr) {
+            Ok(host_and_port) => host_and_port,
+            Err(ha_err) => {
+                return trace_fn_exit_with_err(fn_name, &ha_err.to_string(), None, false);
+            },
+        };
+
+        ctx.attempts += 1;
+        let client_https = self.request_scheme(session) == HTTPS;
+        let (addr, use_https, sni) = match ctx.route {
+            Some(route) => {
+                // Consistent hashing keeps each object on one origin
+                let key = ctx.cache_key.as_deref().unwrap_or(host_hdr);
+                let Some(upstream) = route.select_upstream(client_https, &host_only, key.as_bytes()) else {
+                    let err_msg = format!("No healthy origin available for {host_only}");
+                    trace_fn_exit(fn_name, &err_msg, false);
+                    let mut err = Error::explain(ErrorType::ConnectNoRoute, err_msg);
+                    err.esource = ErrorSource::Upstream;
+                    return Err(err);
+                };
+
+                if let Err(e) = ensure_permitted(&host_only, upstream.addr) {
+                    trace_fn_exit(fn_name, &e.to_string(), false);
+                    return Err(e);
+                }
+
+                ctx.backend = Some(upstream.backend);
+                (upstream.addr, upstream.tls, upstream.sni)
+            },
+            // Only reachable when unknown hosts are passed through
+            None => {
+                let use_https = client_https || port_from_host == Some(DEFAULT_PORT_HTTPS);
+                let port = port_from_host.unwrap_or(if use_https { DEFAULT_PORT_HTTPS } else { DEFAULT_PORT_HTTP });
+                let sni = if use_https { host_only.clone() } else { String::new() };
+
+                match resolve_permitted(&host_only, port).await {
+                    Ok(addr) => (addr, use_https, sni),
+                    Err(e) => {
+                        trace_fn_exit(fn_name, &e.to_string(), false);
+                        return Err(e);
+                    },
+                }
+            },
+        };
+
+        // The origin may resolve to this node itself, for example when a passed-through Host names one of its addresses
+        if self.self_addresses.contains(addr) {
+            let err_msg = format!("Origin {host_only} ({addr}) is this proxy");
+            tracing::warn!("Forwarding loop detected: {err_msg}");
+            proxy_metrics().forwarding_loops.inc();
+            return trace_fn_exit_with_err(
+                fn_name,
+                &err_msg,
+                Some(ErrorType::HTTPStatus(StatusCode::LOOP_DETECTED.as_u16())),
+                false,
+            );
+        }
+
+        tracing::debug!(
+            "     origin: {} ({}) tls={} sni={} attempt={}",
+            host_only,
+            addr,
+            use_https,
+            sni,
+            ctx.attempts
+        );
+        let mut peer = HttpPeer::new(addr, use_https, sni);
+
+        if let Some(route) = ctx.route {
+            route.peer.apply(&mut peer.options);
+        }
+
+        <Self as Trace>::fn_exit(fn_name);
+        Ok(Box::new(peer))
+    }
+
+    // - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+    // Count connection failures against the backend's passive health check.
+    // Nothing has reached the origin, so an idempotent request can be retried, and upstream_peer() then chooses again
+    fn fail_to_connect(
+        &self,
+        session: &mut Session,
+        peer: &HttpPeer,
+        ctx: &mut Self::CTX,
+        mut e: Box<Error>,
+    ) -> Box<Error> {
+        if let (Some(route), Some(backend)) = (ctx.route, ctx.backend) {
+            tracing::debug!("     failed to connect to backend {backend}: {e}");
+            route.report(backend, false);
+        }
+
+        let retry = retry_cfg(ctx).allows(&session.req_header().method, ctx.attempts);
+        if retry {
+            tracing::warn!("Retrying after failing to connect to {peer} (attempt {}): {e}", ctx.attempts);
+        }
+        e.set_retry(retry);
+        e
+    }
+
+    // - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+    // An error after the request has been sent is only retried on a pooled connection, which the origin may have closed
+    // while idle, and only if the request body has not outgrown the retry buffer
+    fn error_while_proxy(
+        &self,
+        peer: &HttpPeer,
+        session: &mut Session,
+        e: Box<Error>,
+        ctx: &mut Self::CTX,
+        client_reused: bool,
+    ) -> Box<Error> {
+        let mut e = e.more_context(format!("Peer: {peer}"));
+
+        if e.esource() == &ErrorSource::Upstream
+            && let (Some(route), Some(backend)) = (ctx.route, ctx.backend)
+        {
+            route.report(backend, false);
+        }
+
+        if retry_cfg(ctx).allows(&session.req_header().method, ctx.attempts) {
+            e.retry.decide_reuse(client_reused && !session.as_ref().retry_buffer_truncated());
+        } else {
+            e.set_retry(false);
+        }
+
+        if e.retry() {
+            tracing::warn!("Retrying after error on pooled connection to {peer} (attempt {}): {e}", ctx.attempts);
+        }
+        e
+    }
+
+    // - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+    // Remember which connection this request uses, so that its reuse can be counted
+    async fn connected_to_upstream(
+        &self,
+        _session: &mut Session,
+        reused: bool,
+        _peer: &HttpPeer,
+        #[cfg(unix)] fd: std::os::unix::io::RawFd,
+        #[cfg(windows)] sock: std::os::windows::io::RawSocket,
+        digest: Option<&Digest>,
+        ctx: &mut Self::CTX,
+    ) -> pingora_error::Result<()> {
+        #[cfg(unix)]
+        let id = fd as u64;
+        #[cfg(windows)]
+        let id = sock;
+
+        ctx.upstream_connection = Some(UpstreamConnection::new(id, reused, digest));
+        Ok(())
+    }
+
+    // - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+    async fn upstream_request_filter(
+        &self,
+        session: &mut Session,
+        upstream_request: &mut RequestHeader,
+        ctx: &mut Self::CTX,
+    ) -> pingora_error::Result<()> {
+        // Identify this node so that the request is rejected if it ever arrives here again
+        upstream_request.append_header("via", via_entry(session.req_header().version))?;
+
+        if let Some(remaining) = max_forwards(upstream_request) {
+            upstream_request.insert_header("max-forwards", remaining.saturating_sub(1))?;
+        }
+
+        // The original authority, which the origin would otherwise lose if the Host header is rewritten below
+        let req = session.req_header();
+        let host = req
+            .headers
+            .get("Host")
+            .and_then(|h| h.to_str().ok())
+            .or_else(|| req.uri.authority().map(|authority| authority.as_str()))
+            .unwrap_or_default()
+            .to_string();
+        add_forwarding_headers(session, upstream_request, &host, self.request_scheme(session))?;
+
+        if let Some(host) = ctx.route.and_then(|route| route.host_rewrite.as_deref()) {
+            tracing::debug!("     rewriting Host to {host}");
+            upstream_request.insert_header("host", host)?;
+        }
+
+        // An HTTP/1.1 connection that has reached its maximum number of requests is closed once this one completes.
+        // HTTP/2 connections are shared by concurrent streams, so are not limited this way
+        if let Some(route) = ctx.route
+            && let Some(max_reuse) = route.peer.max_reuse
+            && let Some(conn) = &ctx.upstream_connection
+            && upstream_request.version != Version::HTTP_2
+            && count_connection_use(conn, max_reuse, route.peer.idle_timeout())
+        {
+            tracing::debug!("     upstream connection reached max_reuse of {max_reuse}");
+            upstream_request.insert_header("connection", "close")?;
+        }
+
+        Ok(())
+    }
+
+    // - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+    fn request_cache_filter(&self, session: &mut Session, ctx: &mut Self::CTX) -> pingora_error::Result<()> {
+        let fn_name = "request_cache_filter";
+        <Self as Trace>::fn_enter(fn_name);
+
+        // RFC 9111 §3: only responses to GET and HEAD are looked up or stored.
+        // A PURGE must still reach the cache, since Pingora only purges when the cache is enabled
+        let method = &session.req_header().method;
+        if !cacheable_method(method) && !is_purge_request(session.req_header()) {
+            // RFC 9111 §4.4: a successful unsafe request invalidates whatever is stored for its URI
+            if invalidates_stored(method) {
+                ctx.invalidates = Some(self.cache_key_callback(session, ctx)?.to_compact());
+            }
+
+            trace_fn_exit(fn_name, "Disk cache not enabled for this method", false);
+            return Ok(());
+        }
+
+        // Requests that loop back to this node never get this far: they are rejected by request_filter() when they
+        // carry our Via entry, or by upstream_peer() when their origin is one of our own addresses
+        session.cache.enable(
+            tiered_cache(),
+            Some(eviction_manager()),
+            None,
+            Some(cache_lock()),
+            Some(cache_lock_overrides()),
+        );
+        tracing::debug!("     Disk cache enabled");
+
+        // Normalise Accept-Encoding before the variance key is calculated or the request is sent to the origin
+        normalize_request(session.req_header_mut());
+
+        if !client_directives_cfg().ignore_client_directives {
+            ctx.client_directives = ClientDirectives::from_request(session.req_header());
+            tracing::debug!("     client directives = {:?}", ctx.client_directives);
+        }
+
+        <Self as Trace>::fn_exit(fn_name);
+        Ok(())
+    }
+
+    // - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+    // Build cache key from tenant+scheme+host+port+path
+    fn cache_key_callback(&self, session: &Session, ctx: &mut Self::CTX) -> pingora_error::Result<CacheKey> {
+        let fn_name = "cache_key_callback";
+        <Self as Trace>::fn_enter(fn_name);
+
+        let host_hdr = session
+            .req_header()
+            .headers
+            .get("Host")
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or_default();
+        let (host_only, port_opt) = match parse_host_authority(host_hdr) {
+            Ok((host_only, port_opt)) => (host_only, port_opt),
+            Err(parse_err) => return trace_fn_exit_with_err(fn_name, &parse_err.to_string(), None, false),
+        };
+        let host_lc = host_only.to_ascii_lowercase();
+        let scheme = self.request_scheme(session);
+        let path_q = session.req_header().uri.path_and_query().map(|pq| pq.as_str()).unwrap_or("/");
+        let primary = format!(
+            "{scheme}://{}{}",
+            key_authority(&host_lc, port_opt, scheme),
+            normalize_path_and_query(&host_lc, path_q)
+        );
+
+        tracing::debug!("     cache key primary = {primary}");
+        <Self as Trace>::fn_exit(fn_name);
+
+        // The tenant is both the namespace, which keeps tenants' keys apart, and the user tag, which is all the storage
+        // layer sees when an object is purged or evicted
+        let tenant = resolve_tenant(session, &host_lc);
+        tracing::debug!("     cache key tenant = {tenant:?}");
+        let key = CacheKey::new(tenant.as_bytes(), primary.as_bytes(), tenant.as_str());
+        ctx.cache_key = Some(primary);
+        Ok(key)
+    }
+
+    // - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+    // A PURGE request has already been authorised by request_filter().
+    // Pingora invalidates the object stored under the key built by cache_key_callback(), which purges the primary slot
+    // together with all of its variants, then answers 200 if it was found or 404 if it was not
+    fn is_purge(&self, session: &Session, _ctx: &Self::CTX) -> bool {
+        is_purge_request(session.req_header())
+    }
+
+    fn purge_response_filter(
+        &self,
+        session: &Session,
+        ctx: &mut Self::CTX,
+        purge_status: PurgeStatus,
+        _purge_response: &mut Cow<'static, ResponseHeader>,
+    ) -> pingora_error::Result<()> {
+        let result = match purge_status {
+            PurgeStatus::Found => "purged",
+            PurgeStatus::NotFound => "not_found",
+            PurgeStatus::NoCache => "not_cacheable",
+            PurgeStatus::Error(_) => "error",
+        };
+
+        tracing::info!(
+            "PURGE {} from {}: {result}",
+            ctx.cache_key.as_deref().unwrap_or_default(),
+            session.client_addr().map(|addr| addr.to_string()).unwrap_or_default()
+        );
+        proxy_metrics().purge_requests.with_label_values(&[result]).inc();
+        Ok(())
+    }
+
+    // - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+    // Select the variant of a cached response that matches this request's values for the headers listed in Vary
+    fn cache_vary_filter(&self, meta: &CacheMeta, _ctx: &mut Self::CTX, req: &RequestHeader) -> Option<HashBinary> {
+        variance_key(meta.response_header(), req)
+    }
+
+    // - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+    async fn cache_hit_filter(
+        &self,
+        _session: &mut Session,
+        meta: &CacheMeta,
+        _hit: &mut Box<dyn HandleHit + Send + Sync>,
+        is_fresh: bool,
+        ctx: &mut Self::CTX,
+    ) -> pingora_error::Result<Option<ForcedInvalidationKind>> {
+        ctx.cache_tier = meta.extensions().get::<CacheTier>().copied();
+
+        // A ban added since the object was stored means it must be fetched again, whatever its freshness
+        if let Some(key) = &ctx.cache_key
+            && let Some(ban) = disk_cache().bans.matching(key, meta.created())
+        {
+            tracing::debug!("     cached object invalidated by ban {ban}");
+            proxy_metrics().banned_hits.inc();
+            ctx.cache_fwd = Some(ForwardReason::Banned);
+            return Ok(Some(ForcedInvalidationKind::ForceMiss));
+        }
+
+        // The client may demand a fresher response than the one we hold, in which case it must be revalidated
+        if is_fresh && ctx.client_directives.requires_revalidation(meta, SystemTime::now()) {
+            tracing::debug!("     client directives force revalidation");
+            ctx.cache_fwd = Some(ForwardReason::Request);
+            return Ok(Some(ForcedInvalidationKind::ForceExpired));
+        }
+
+        if !is_fresh {
+            ctx.cache_fwd = Some(ForwardReason::Stale);
+        }
+
+        Ok(None)
+    }
+
+    // - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+    // Pingora also calls this for a hit that cache_hit_filter() has turned into a miss
+    fn cache_miss(&self, session: &mut Session, ctx: &mut Self::CTX) {
+        if ctx.cache_fwd != Some(ForwardReason::Banned) {
+            ctx.cache_fwd = Some(ForwardReason::UriMiss);
+        }
+        session.cache.cache_miss();
+    }
+
+    // - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+    // Only called when the response will not be served from the cache
+    async fn proxy_upstream_filter(&self, session: &mut Session, ctx: &mut Self::CTX) -> pingora_error::Result<bool> {
+        // RFC 9111 §5.2.1.7: only-if-cached must be answered with 504 rather than contacting the origin.
+        // A background stale-while-revalidate refresh runs as a copy of the client's request, so is exempt
+        if ctx.client_directives.only_if_cached && session.subrequest_ctx.is_none() {
+            tracing::debug!("     only-if-cached request not satisfied from cache");
+            session.respond_error(StatusCode::GATEWAY_TIMEOUT.as_u16()).await?;
+            return Ok(false);
+        }
+
+        Ok(true)
+    }
+
+    // - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+    fn response_cache_filter(
+        &self,
+        session: &Session,
+        resp: &ResponseHeader,
+        ctx: &mut Self::CTX,
+    ) -> pingora_error::Result<RespCacheable> {
+        let fn_name = "response_cache_filter";
+        <Self as Trace>::fn_enter(fn_name);
+
+        // When a stale entry has been revalidated, the 304 supplies the current metadata for the stored response
+        let refreshed;
+        let resp = match ctx.not_modified.take() {
+            Some(not_modified) => {
+                tracing::debug!("     refreshing stored headers from 304 Not Modified");
+                refreshed = refresh_stored_header(resp, &not_modified);
+                &refreshed
+            },
+            None => resp,
+        };
+
+        // Never store anything a shared cache must not reuse for another client.
+        // CDN-Cache-Control or Surrogate-Control, when present, take the place of Cache-Control
+        let cc = edge_cache_control(resp);
+        let mut stored = match shared_cacheability(session.req_header(), resp, cc.as_ref(), cacheability_cfg()) {
+            Ok(stored) => stored,
+            Err(reason) => {
+                trace_fn_exit(fn_name, &format!("Not caching response: {}", reason.as_str()), false);
+                return Ok(RespCacheable::Uncacheable(reason));
+            },
+        };
+
+        // Only an object that is not yet stored needs admitting; an expired one is already on disk
+        if session.cache.phase() == CachePhase::Miss {
+            let admitted = admission_filter().admit(&session.cache.cache_key().primary_bin());
+            let result = if admitted { "admitted" } else { "rejected" };
+            proxy_metrics().cache_admissions.with_label_values(&[result]).inc();
+
+            if !admitted {
+                trace_fn_exit(fn_name, "Not caching response: not yet requested often enough", false);
+                return Ok(RespCacheable::Uncacheable(NoCacheReason::Custom("NotAdmitted")));
+            }
+        }
+
+        // Otherwise, make it cacheable for as long as the origin allows
+        let now = SystemTime::now();
+
+        // RFC 9110 §6.6.1: a response without a Date must be given one, otherwise its age cannot be calculated on a hit
+        if !stored.headers.contains_key("date") {
+            stored.insert_header("date", HttpDate::from(now).to_string()).ok();
+        }
+        let freshness = Freshness::from_response(resp, cc.as_ref(), now, freshness_cfg());
+        tracing::debug!(
+            "     freshness lifetime = {}s, age = {}s, stale-while-revalidate = {}s, stale-if-error = {}s",
+            freshness.lifetime.as_secs(),
+            freshness.age.as_secs(),
+            freshness.stale_while_revalidate,
+            freshness.stale_if_error
+        );
+
+        let meta = CacheMeta::new(
+            freshness.fresh_until(now),
+            now,
+            freshness.stale_while_revalidate,
+            freshness.stale_if_error,
+            stored,
+        );
+        let response = RespCacheable::Cacheable(meta);
+
+        <Self as Trace>::fn_exit(fn_name);
+        Ok(response)
+    }
+
+    // - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+    // Pingora only calls this filter once it has checked that the stale entry is still within the relevant window
+    fn should_serve_stale(&self, session: &mut Session, ctx: &mut Self::CTX, error: Option<&Error>) -> bool {
+        // The client's own directives can forbid stale content whatever the origin allows
+        if let Some(meta) = session.cache.maybe_cache_meta()
+            && !ctx.client_directives.accepts_stale(meta, SystemTime::now())
+        {
+            tracing::debug!("     client directives forbid serving stale");
+            return false;
+        }
+
+        match error {
+            // stale-while-revalidate: serve the stale entry while a background request refreshes it
+            None => {
+                proxy_metrics().stale_while_revalidate.inc();
+                true
+            },
+            // stale-if-error: only an origin failure (including a 5xx status) allows the last cached copy to be used
+            Some(e) if e.esource() == &ErrorSource::Upstream => {
+                tracing::debug!("     serving stale after origin error: {e}");
+                proxy_metrics().stale_if_error.inc();
+                true
+            },
+            Some(_) => false,
+        }
+    }
+
+    // - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+    fn upstream_response_filter(
+        &self,
+        session: &mut Session,
+        upstream_resp: &mut ResponseHeader,
+        ctx: &mut Self::CTX,
+    ) -> pingora_error::Result<()> {
+        ctx.upstream_status = Some(upstream_resp.status.as_u16());
+
+        if let (Some(route), Some(backend)) = (ctx.route, ctx.backend) {
+            route.report(backend, !upstream_resp.status.is_server_error());
+        }
+
+        // A stale entry is being revalidated.
+        // Pingora has already sent the origin If-None-Match/If-Modified-Since from the stored ETag/Last-Modified
+        if session.cache.phase() == CachePhase::Stale {
+            proxy_metrics().revalidations.inc();
+
+            if upstream_resp.status == StatusCode::NOT_MODIFIED {
+                proxy_metrics().revalidated_not_modified.inc();
+                ctx.not_modified = Some(upstream_resp.clone());
+            }
+        }
+
+        upstream_resp.insert_header("x-cdn-cache", "MISS").ok();
+        Ok(())
+    }
+
+    // - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+    async fn response_filter(
+        &self,
+        session: &mut Session,
+        resp: &mut ResponseHeader,
+        ctx: &mut Self::CTX,
+    ) -> pingora_error::Result<()> {
+        // Only a non-error response from the origin shows that the unsafe request may have changed the resource
+        if let Some(key) = ctx.invalidates.take()
+            && ctx.upstream_status.is_some_and(|status| (200..400).contains(&status))
+            && tiered_cache().invalidate(&key).await
+        {
+            tracing::debug!("     stored object invalidated by {} request", session.req_header().method);
+            proxy_metrics().unsafe_method_invalidations.inc();
+        }
+
+        let cache_status = CacheStatus::from_session(session, ctx);
+        tracing::debug!("     cache outcome = {:?}", cache_status);
+
+        // Counting by status shows how much of the origin's load negative caching and redirect caching absorb
+        let status_label = resp.status.as_str();
+        if cache_status.outcome.served_from_cache() {
+            proxy_metrics().served_by_status.with_label_values(&[status_label]).inc();
+        }
+        if cache_status.stored {
+            proxy_metrics().stored_by_status.with_label_values(&[status_label]).inc();
+        }
+        if cache_status.collapsed {
+            proxy_metrics().collapsed_requests.inc();
+        }
+
+        // RFC 9111 §5.1: a response served from the cache must say how long it has been since the origin generated it
+        if cache_status.outcome.served_from_cache()
+            && let Some(meta) = session.cache.maybe_cache_meta()
+        {
+            resp.insert_header("age", current_age(meta, SystemTime::now()).as_secs()).ok();
+        }
+
+        let state = if cache_status.outcome.is_hit() {
+            "HIT" // fetched from cache
+        } else {
+            "MISS" // fetched from origin
+        };
+
+        // Tags and edge cache directives are only meant for this cache
+        for name in TAG_HEADERS.into_iter().chain(EDGE_CONTROL_HEADERS) {
+            resp.remove_header(name);
+        }
+
+        resp.insert_header("x-cdn-cache", state).ok();
+        // RFC 9211 §2: each cache appends its own member to any Cache-Status list received from upstream
+        resp.append_header("cache-status", cache_status.header_value()).ok();
+        Ok(())
+    }
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// Requests for unknown hosts that are passed through use the default retry policy
+fn retry_cfg(ctx: &EdgeCtx) -> RetryCfg {
+    ctx.route.map(|route| route.retry).unwrap_or_default()
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// The authority part of a cache key. The port is only included when it is not the default for the scheme, so that
+// "example.com" and "example.com:443" share an entry over https, but "example.com:8080" does not
+fn key_authority(host_lc: &str, port: Option<u16>, scheme: &str) -> String {
+    let default_port = if scheme == HTTPS { DEFAULT_PORT_HTTPS } else { DEFAULT_PORT_HTTP };
+    let host = if host_lc.contains(':') { format!("[{host_lc}]") } else { host_lc.to_string() };
+
+    match port {
+        Some(port) if port != default_port => format!("{host}:{port}"),
+        _ => host,
+    }
+}
@@ -0,0 +1,374 @@
+use crate::{
+    config::edge_config,
+    consts::{DEFAULT_PORT_HTTP, DEFAULT_PORT_HTTPS},
+    metrics::proxy_metrics,
+    proxy::routing::Route,
+    utils::parse_host_authority,
+};
+
+use pingora::{
+    http::RequestHeader,
+    lb::{
+        health_check::HttpHealthCheck,
+        selection::{Consistent, RoundRobin},
+        Backend, LoadBalancer,
+    },
+    prelude::background_service,
+};
+use pingora_core::services::Service;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeSet, HashMap},
+    error::Error,
+    fmt,
+    net::{SocketAddr, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+// Bounds the search for an acceptable backend, as required by LoadBalancer::select_with()
+const MAX_SELECTION_ITERATIONS: usize = 256;
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+/// How a backend is chosen from a route's upstreams
+///
+/// `consistent_hash` keys on the primary cache key, so each object is always fetched from the same origin while that
+/// origin is available
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Selection {
+    #[default]
+    RoundRobin,
+    Weighted,
+    ConsistentHash,
+}
+
+/// An upstream is either a `host[:port]` string or an object giving its weight as well
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum UpstreamSpec {
+    Addr(String),
+    Weighted { addr: String, weight: usize },
+}
+
+impl UpstreamSpec {
+    pub fn addr(&self) -> &str {
+        match self {
+            UpstreamSpec::Addr(addr) | UpstreamSpec::Weighted { addr, .. } => addr,
+        }
+    }
+
+    fn weight(&self) -> usize {
+        match self {
+            UpstreamSpec::Addr(_) => 1,
+            UpstreamSpec::Weighted { weight, .. } => *weight,
+        }
+    }
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+/// Active health check: a periodic `GET` that must be answered with `200 OK`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HealthCheckCfg {
+    pub path: String,
+    /// The `Host` header of the probe, which defaults to the route's `host_rewrite` or first host name
+    pub host: Option<String>,
+    pub interval_secs: u64,
+    pub timeout_secs: u64,
+    /// Consecutive successful probes needed before an unhealthy backend is used again
+    pub healthy_threshold: usize,
+    /// Consecutive failed probes needed before a healthy backend is taken out of use
+    pub unhealthy_threshold: usize,
+}
+
+impl HealthCheckCfg {
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.path.starts_with('/') || RequestHeader::build("GET", self.path.as_bytes(), None).is_err() {
+            return Err(format!("health check path \"{}\" must be a valid absolute path", self.path));
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for HealthCheckCfg {
+    fn default() -> Self {
+        Self {
+            path: "/".to_string(),
+            host: None,
+            interval_secs: 10,
+            timeout_secs: 2,
+            healthy_threshold: 2,
+            unhealthy_threshold: 3,
+        }
+    }
+}
+
+/// Passive health check: a backend is ejected for `eject_secs` after `max_failures` consecutive connection errors or
+/// `5xx` responses
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PassiveCfg {
+    pub max_failures: u32,
+    pub eject_secs: u64,
+}
+
+impl PassiveCfg {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_failures == 0 {
+            return Err("passive max_failures must be at least 1".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for PassiveCfg {
+    fn default() -> Self {
+        Self {
+            max_failures: 5,
+            eject_secs: 30,
+        }
+    }
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+enum Balancer {
+    RoundRobin(Arc<LoadBalancer<RoundRobin>>),
+    Consistent(Arc<LoadBalancer<Consistent>>),
+}
+
+impl Balancer {
+    fn select_with<F>(&self, key: &[u8], accept: F) -> Option<Backend>
+    where
+        F: Fn(&Backend, bool) -> bool,
+    {
+        match self {
+            Balancer::RoundRobin(lb) => lb.select_with(key, MAX_SELECTION_ITERATIONS, accept),
+            Balancer::Consistent(lb) => lb.select_with(key, MAX_SELECTION_ITERATIONS, accept),
+        }
+    }
+
+    fn backends(&self) -> Vec<(Backend, bool)> {
+        let backends = match self {
+            Balancer::RoundRobin(lb) => lb.backends(),
+            Balancer::Consistent(lb) => lb.backends(),
+        };
+
+        backends
+            .get_backend()
+            .iter()
+            .map(|backend| (backend.clone(), backends.ready(backend)))
+            .collect()
+    }
+}
+
+// Consecutive failures seen by the proxy itself, and when an ejected backend may be tried again (0 if not ejected)
+#[derive(Default)]
+struct PassiveState {
+    failures: AtomicU32,
+    ejected_until_ms: AtomicU64,
+}
+
+/// The per-backend state shown by the inspector
+#[derive(Debug, Serialize)]
+pub struct BackendStatus {
+    pub addr: String,
+    pub weight: usize,
+    /// The verdict of the active health check (always `true` if there is none)
+    pub healthy: bool,
+    pub ejected: bool,
+    pub consecutive_failures: u32,
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+/// The backends of one route, together with their active and passive health
+pub struct OriginPool {
+    balancer: Balancer,
+    passive: HashMap<SocketAddr, PassiveState>,
+    passive_cfg: PassiveCfg,
+}
+
+impl fmt::Debug for OriginPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OriginPool").field("backends", &self.passive.keys()).finish()
+    }
+}
+
+impl OriginPool {
+    /// Choose a backend that is healthy and not ejected.
+    /// If every backend has been ejected, passive ejection is ignored rather than failing every request
+    pub fn select(&self, key: &[u8]) -> Option<SocketAddr> {
+        self.balancer
+            .select_with(key, |backend, healthy| healthy && !self.is_ejected(backend))
+            .or_else(|| self.balancer.select_with(key, |_, healthy| healthy))
+            .and_then(|backend| backend.as_inet().copied())
+    }
+
+    pub fn report_success(&self, addr: SocketAddr) {
+        if let Some(state) = self.passive.get(&addr) {
+            state.failures.store(0, Ordering::Relaxed);
+            state.ejected_until_ms.store(0, Ordering::Relaxed);
+        }
+    }
+
+    pub fn report_failure(&self, addr: SocketAddr) {
+        let Some(state) = self.passive.get(&addr) else { return };
+        let failures = state.failures.fetch_add(1, Ordering::Relaxed) + 1;
+
+        if failures >= self.passive_cfg.max_failures {
+            let until = now_ms() + self.passive_cfg.eject_secs * 1000;
+
+            if state.ejected_until_ms.swap(until, Ordering::Relaxed) < now_ms() {
+                tracing::warn!("Ejecting backend {addr} after {failures} consecutive failures");
+                proxy_metrics().backend_ejections.inc();
+            }
+        }
+    }
+
+    pub fn status(&self) -> Vec<BackendStatus> {
+        self.balancer
+            .backends()
+            .into_iter()
+            .map(|(backend, healthy)| {
+                let state = backend.as_inet().and_then(|addr| self.passive.get(addr));
+
+                BackendStatus {
+                    addr: backend.addr.to_string(),
+                    weight: backend.weight,
+                    healthy,
+                    ejected: self.is_ejected(&backend),
+                    consecutive_failures: state.map_or(0, |state| state.failures.load(Ordering::Relaxed)),
+                }
+            })
+            .collect()
+    }
+
+    fn is_ejected(&self, backend: &Backend) -> bool {
+        backend
+            .as_inet()
+            .and_then(|addr| self.passive.get(addr))
+            .is_some_and(|state| state.ejected_until_ms.load(Ordering::Relaxed) > now_ms())
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+/// Build the origin pool of every route, returning the background services that run their active health checks
+///
+/// Upstream host names are resolved once, here, so DNS changes are only picked up on restart.
+/// An upstream without a port uses the route's `port`, or the default port for the route's `tls` setting; if `tls` is
+/// not set either, the port is chosen per request according to the scheme the client used (port 0 stands for this)
+pub fn start_origin_pools() -> Result<Vec<Box<dyn Service>>, Box<dyn Error>> {
+    let mut services: Vec<Box<dyn Service>> = Vec::new();
+
+    for (idx, route) in edge_config().routing.routes.iter().enumerate() {
+        let backends = resolve_backends(route)?;
+        let passive = backends
+            .iter()
+            .filter_map(|backend| backend.as_inet().map(|addr| (*addr, PassiveState::default())))
+            .collect();
+        let health_check = route.health_check.as_ref().map(|cfg| http_health_check(route, cfg));
+        let interval = route.health_check.as_ref().map(|cfg| Duration::from_secs(cfg.interval_secs));
+        let service_name = format!("origin health check for route {idx}");
+
+        let balancer = match route.selection {
+            Selection::RoundRobin | Selection::Weighted => {
+                let mut lb = LoadBalancer::<RoundRobin>::try_from_iter(backends)?;
+                lb.health_check_frequency = interval;
+
+                match health_check {
+                    Some(hc) => {
+                        lb.set_health_check(hc);
+                        let service = background_service(&service_name, lb);
+                        let lb = service.task();
+                        services.push(Box::new(service));
+                        Balancer::RoundRobin(lb)
+                    },
+                    None => Balancer::RoundRobin(Arc::new(lb)),
+                }
+            },
+            Selection::ConsistentHash => {
+                let mut lb = LoadBalancer::<Consistent>::try_from_iter(backends)?;
+                lb.health_check_frequency = interval;
+
+                match health_check {
+                    Some(hc) => {
+                        lb.set_health_check(hc);
+                        let service = background_service(&service_name, lb);
+                        let lb = service.task();
+                        services.push(Box::new(service));
+                        Balancer::Consistent(lb)
+                    },
+                    None => Balancer::Consistent(Arc::new(lb)),
+                }
+            },
+        };
+
+        route
+            .set_pool(OriginPool {
+                balancer,
+                passive,
+                passive_cfg: route.passive,
+            })
+            .map_err(|_| format!("Origin pool for route {idx} has already been started"))?;
+    }
+
+    Ok(services)
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+fn resolve_backends(route: &Route) -> Result<BTreeSet<Backend>, Box<dyn Error>> {
+    let default_port = match route.tls {
+        Some(true) => DEFAULT_PORT_HTTPS,
+        Some(false) => DEFAULT_PORT_HTTP,
+        None => 0,
+    };
+    let mut backends = BTreeSet::new();
+
+    for spec in &route.upstreams {
+        let (host, port) = parse_host_authority(spec.addr())?;
+        let port = port.or(route.port).unwrap_or(default_port);
+        let weight = if route.selection == Selection::RoundRobin { 1 } else { spec.weight() };
+        let addrs = (host.as_str(), port)
+            .to_socket_addrs()
+            .map_err(|e| format!("Unable to resolve upstream {}: {e}", spec.addr()))?;
+
+        // Every address of a multi-homed origin becomes a backend in its own right
+        for addr in addrs {
+            backends.insert(Backend::new_with_weight(&addr.to_string(), weight)?);
+        }
+    }
+
+    Ok(backends)
+}
+
+fn http_health_check(route: &Route, cfg: &HealthCheckCfg) -> Box<HttpHealthCheck> {
+    let host = cfg
+        .host
+        .as_deref()
+        .or(route.host_rewrite.as_deref())
+        .or(route.hosts.first().map(String::as_str))
+        .unwrap_or("localhost");
+    let tls = route.tls.unwrap_or(false);
+    let mut hc = HttpHealthCheck::new(host, tls);
+
+    if let Some(sni) = route.sni.as_deref().filter(|_| tls) {
+        hc.peer_template.sni = sni.to_string();
+    }
+
+    hc.req.set_uri(cfg.path.parse().expect("health check path is validated when the configuration is loaded"));
+    hc.peer_template.options.connection_timeout = Some(Duration::from_secs(cfg.timeout_secs));
+    hc.peer_template.options.read_timeout = Some(Duration::from_secs(cfg.timeout_secs));
+    hc.consecutive_success = cfg.healthy_threshold;
+    hc.consecutive_failure = cfg.unhealthy_threshold;
+
+    Box::new(hc)
+}
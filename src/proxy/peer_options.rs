@@ -0,0 +1,212 @@
+use crate::consts::ONE_HOUR;
+
+use pingora::http::Method;
+use pingora_core::{
+    protocols::{l4::ext::TcpKeepalive, Digest},
+    upstreams::peer::PeerOptions,
+};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant, SystemTime},
+};
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+/// How connections to a route's origins are made and reused
+///
+/// ```json
+/// "peer": {
+///   "connect_timeout_ms": 1000, "total_connect_timeout_ms": 3000, "read_timeout_ms": 30000,
+///   "write_timeout_ms": 30000, "idle_timeout_secs": 60, "max_reuse": 100, "http2": true,
+///   "tcp_keepalive": { "idle_secs": 60, "interval_secs": 10, "count": 5 }
+/// }
+/// ```
+///
+/// Any timeout that is not given is left at Pingora's default.
+/// `total_connect_timeout_ms` includes the TLS handshake, whereas `connect_timeout_ms` only covers the TCP connection.
+/// `idle_timeout_secs` is how long an unused connection is kept in the pool, and `max_reuse` is the number of requests
+/// after which an HTTP/1.1 connection is closed rather than returned to the pool.
+/// `http2` offers HTTP/2 to TLS origins, falling back to HTTP/1.1 if the origin does not accept it
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PeerCfg {
+    pub connect_timeout_ms: Option<u64>,
+    pub total_connect_timeout_ms: Option<u64>,
+    pub read_timeout_ms: Option<u64>,
+    pub write_timeout_ms: Option<u64>,
+    pub idle_timeout_secs: Option<u64>,
+    pub max_reuse: Option<u32>,
+    pub http2: bool,
+    pub tcp_keepalive: Option<KeepaliveCfg>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeepaliveCfg {
+    pub idle_secs: u64,
+    pub interval_secs: u64,
+    pub count: usize,
+}
+
+impl Default for KeepaliveCfg {
+    fn default() -> Self {
+        Self {
+            idle_secs: 60,
+            interval_secs: 10,
+            count: 5,
+        }
+    }
+}
+
+impl PeerCfg {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_reuse == Some(0) {
+            return Err("peer max_reuse must be at least 1".to_string());
+        }
+
+        match &self.tcp_keepalive {
+            Some(ka) if ka.count == 0 => Err("peer tcp_keepalive count must be at least 1".to_string()),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn apply(&self, options: &mut PeerOptions) {
+        let ms = |value: Option<u64>| value.map(Duration::from_millis);
+
+        options.connection_timeout = ms(self.connect_timeout_ms).or(options.connection_timeout);
+        options.total_connection_timeout = ms(self.total_connect_timeout_ms).or(options.total_connection_timeout);
+        options.read_timeout = ms(self.read_timeout_ms).or(options.read_timeout);
+        options.write_timeout = ms(self.write_timeout_ms).or(options.write_timeout);
+        options.idle_timeout = self.idle_timeout_secs.map(Duration::from_secs).or(options.idle_timeout);
+
+        if self.http2 {
+            options.set_http_version(2, 1);
+        }
+
+        if let Some(ka) = &self.tcp_keepalive {
+            options.tcp_keepalive = Some(TcpKeepalive {
+                idle: Duration::from_secs(ka.idle_secs),
+                interval: Duration::from_secs(ka.interval_secs),
+                count: ka.count,
+                #[cfg(target_os = "linux")]
+                user_timeout: Duration::ZERO,
+            });
+        }
+    }
+
+    /// How long the connection pool keeps an unused connection.
+    /// Without `idle_timeout_secs`, Pingora keeps it until the origin closes it, which is assumed to be within the hour
+    pub fn idle_timeout(&self) -> Duration {
+        self.idle_timeout_secs.map_or(ONE_HOUR, Duration::from_secs)
+    }
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+/// How often a request that failed to reach the origin is tried again
+///
+/// Only idempotent requests are retried.
+/// A failure to connect is always retried, but a failure after the request has been sent is only retried if the
+/// connection was reused from the pool (the origin may have closed it while idle) and the request body is still held
+/// in full.
+/// Each retry selects a backend afresh, so a round-robin route moves on to another origin
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryCfg {
+    pub max_retries: u32,
+}
+
+impl Default for RetryCfg {
+    fn default() -> Self {
+        Self { max_retries: 1 }
+    }
+}
+
+impl RetryCfg {
+    /// Whether a request that has already been sent `attempts` times may be sent again
+    pub fn allows(&self, method: &Method, attempts: u32) -> bool {
+        is_idempotent(method) && attempts <= self.max_retries
+    }
+}
+
+// RFC 9110 §9.2.2
+fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE | Method::PUT | Method::DELETE
+    )
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+/// The upstream connection a request was sent over
+///
+/// Pingora gives no way to attach state to a pooled connection, so a connection is identified by its file descriptor
+/// together with the time it was established, which tells apart two connections that were given the same descriptor
+#[derive(Debug, Clone, Copy)]
+pub struct UpstreamConnection {
+    pub fd: u64,
+    pub established: Option<SystemTime>,
+    pub reused: bool,
+}
+
+impl UpstreamConnection {
+    pub fn new(fd: u64, reused: bool, digest: Option<&Digest>) -> Self {
+        let established = digest
+            .and_then(|digest| digest.timing_digest.first().cloned().flatten())
+            .map(|timing| timing.established_ts);
+
+        Self { fd, established, reused }
+    }
+}
+
+// The number of requests sent over each open upstream connection, keyed by its file descriptor.
+// Nothing reports when a pooled connection closes, so an entry is replaced when its descriptor is given to a new
+// connection, and dropped once it has been idle for longer than the pool would keep the connection
+struct ConnectionUses {
+    by_fd: HashMap<u64, Uses>,
+    pruned: Instant,
+}
+
+struct Uses {
+    established: Option<SystemTime>,
+    count: u32,
+    last_used: Instant,
+}
+
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+static CONNECTION_USES: OnceLock<Mutex<ConnectionUses>> = OnceLock::new();
+
+/// Count a request sent over an upstream connection, returning `true` if the connection has now reached `max_reuse`
+/// and must be closed once this request completes.
+/// `idle_timeout` is how long the connection pool keeps an unused connection
+pub fn count_connection_use(conn: &UpstreamConnection, max_reuse: u32, idle_timeout: Duration) -> bool {
+    let mut uses = CONNECTION_USES
+        .get_or_init(|| {
+            Mutex::new(ConnectionUses {
+                by_fd: HashMap::new(),
+                pruned: Instant::now(),
+            })
+        })
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    let now = Instant::now();
+    if now.duration_since(uses.pruned) >= PRUNE_INTERVAL {
+        uses.by_fd.retain(|_, entry| now.duration_since(entry.last_used) < idle_timeout);
+        uses.pruned = now;
+    }
+
+    let count = match uses.by_fd.get(&conn.fd) {
+        Some(entry) if conn.reused && entry.established == conn.established => entry.count + 1,
+        _ => 1,
+    };
+
+    if count >= max_reuse {
+        uses.by_fd.remove(&conn.fd);
+        true
+    } else {
+        uses.by_fd.insert(conn.fd, Uses { established: conn.established, count, last_used: now });
+        false
+    }
+}
@@ -0,0 +1,178 @@
+use crate::{
+    consts::{DEFAULT_PURGE_ALLOW_CIDRS, PURGE_MODE_HEADER, PURGE_SECRET_HEADER},
+    disk_cache::soft_purge::PurgeMode,
+    proxy::trusted_proxies::parse_cidrs,
+    utils::env_var_or_str,
+};
+
+use ipnet::IpNet;
+use pingora::{http::RequestHeader, prelude::Session};
+use std::{net::IpAddr, sync::OnceLock};
+
+const PURGE_METHOD: &str = "PURGE";
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// A PURGE request is accepted from these networks, or from anywhere if it carries the shared secret.
+// With neither configured, every PURGE request is refused
+pub struct PurgeCfg {
+    pub allow: Vec<IpNet>,
+    pub secret: Option<String>,
+}
+
+static PURGE_CFG: OnceLock<PurgeCfg> = OnceLock::new();
+pub fn purge_cfg() -> &'static PurgeCfg {
+    PURGE_CFG.get_or_init(|| PurgeCfg {
+        allow: parse_cidrs(&env_var_or_str("PURGE_ALLOW_CIDRS", DEFAULT_PURGE_ALLOW_CIDRS)),
+        secret: std::env::var("PURGE_SECRET").ok().filter(|secret| !secret.is_empty()),
+    })
+}
+
+impl PurgeCfg {
+    pub fn permits(&self, session: &Session) -> bool {
+        let client = session.client_addr().and_then(|addr| addr.as_inet()).map(|inet| inet.ip());
+        let secret = session.req_header().headers.get(PURGE_SECRET_HEADER).map(|v| v.as_bytes());
+
+        self.permits_client(client, secret)
+    }
+
+    /// Whether a client at this address, offering this `X-Purge-Secret` value, may purge
+    fn permits_client(&self, client: Option<IpAddr>, secret: Option<&[u8]>) -> bool {
+        let from_allowed_network = client.is_some_and(|ip| {
+            let ip = ip.to_canonical();
+            self.allow.iter().any(|net| net.contains(&ip))
+        });
+
+        from_allowed_network || self.has_secret(secret)
+    }
+
+    /// Whether the value of an `X-Purge-Secret` header matches the configured secret
+    pub fn has_secret(&self, given: Option<&[u8]>) -> bool {
+        self.secret
+            .as_ref()
+            .zip(given)
+            .is_some_and(|(secret, given)| constant_time_eq(given, secret.as_bytes()))
+    }
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+pub fn is_purge_request(req: &RequestHeader) -> bool {
+    req.method.as_str() == PURGE_METHOD
+}
+
+/// The mode requested in `X-Purge-Mode`, which defaults to a hard purge.
+/// An unrecognised mode gives `None`, as guessing could delete an object the client only wanted to expire
+pub fn purge_mode(req: &RequestHeader) -> Option<PurgeMode> {
+    match req.headers.get(PURGE_MODE_HEADER) {
+        Some(value) => value.to_str().ok().and_then(PurgeMode::parse),
+        None => Some(PurgeMode::Hard),
+    }
+}
+
+// The time taken must not reveal how much of the secret a guess got right
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pingora::http::Method;
+
+    fn cfg(allow: &str, secret: Option<&str>) -> PurgeCfg {
+        PurgeCfg {
+            allow: parse_cidrs(allow),
+            secret: secret.map(str::to_string),
+        }
+    }
+
+    fn ip(addr: &str) -> Option<IpAddr> {
+        Some(addr.parse().unwrap())
+    }
+
+    fn purge_request(mode: Option<&str>) -> RequestHeader {
+        let mut req = RequestHeader::build(PURGE_METHOD, b"/page", None).unwrap();
+        if let Some(mode) = mode {
+            req.insert_header(PURGE_MODE_HEADER, mode).unwrap();
+        }
+        req
+    }
+
+    #[test]
+    fn clients_in_allowed_networks_are_permitted() {
+        let cfg = cfg("10.0.0.0/8, 2001:db8::/32", None);
+
+        assert!(cfg.permits_client(ip("10.1.2.3"), None));
+        assert!(cfg.permits_client(ip("2001:db8::1"), None));
+        // An IPv4-mapped address is matched against the IPv4 networks
+        assert!(cfg.permits_client(ip("::ffff:10.1.2.3"), None));
+    }
+
+    #[test]
+    fn clients_outside_allowed_networks_are_refused() {
+        let cfg = cfg("10.0.0.0/8", None);
+
+        assert!(!cfg.permits_client(ip("11.0.0.1"), None));
+        assert!(!cfg.permits_client(ip("2001:db8::1"), None));
+        assert!(!cfg.permits_client(None, None));
+    }
+
+    #[test]
+    fn correct_secret_is_permitted_from_anywhere() {
+        let cfg = cfg("10.0.0.0/8", Some("s3cret"));
+
+        assert!(cfg.has_secret(Some(b"s3cret")));
+        assert!(cfg.permits_client(ip("203.0.113.1"), Some(b"s3cret")));
+        assert!(cfg.permits_client(None, Some(b"s3cret")));
+    }
+
+    #[test]
+    fn wrong_or_missing_secret_is_refused() {
+        let cfg = cfg("", Some("s3cret"));
+
+        for given in [Some(&b"wrong!"[..]), Some(b"s3cre"), Some(b"s3cret "), Some(b""), None] {
+            assert!(!cfg.has_secret(given), "{given:?}");
+            assert!(!cfg.permits_client(ip("203.0.113.1"), given), "{given:?}");
+        }
+    }
+
+    #[test]
+    fn no_secret_configured_refuses_every_secret() {
+        let cfg = cfg("", None);
+
+        assert!(!cfg.has_secret(Some(b"")));
+        assert!(!cfg.has_secret(Some(b"anything")));
+    }
+
+    #[test]
+    fn constant_time_eq_compares_whole_values() {
+        assert!(constant_time_eq(b"abc", b"abc"));
+        assert!(!constant_time_eq(b"abc", b"abd"));
+        assert!(!constant_time_eq(b"abc", b"abcd"));
+        assert!(constant_time_eq(b"", b""));
+    }
+
+    #[test]
+    fn purge_mode_defaults_to_hard() {
+        assert_eq!(purge_mode(&purge_request(None)), Some(PurgeMode::Hard));
+        assert_eq!(purge_mode(&purge_request(Some("hard"))), Some(PurgeMode::Hard));
+    }
+
+    #[test]
+    fn purge_mode_soft() {
+        assert_eq!(purge_mode(&purge_request(Some("soft"))), Some(PurgeMode::Soft));
+        assert_eq!(purge_mode(&purge_request(Some(" SOFT "))), Some(PurgeMode::Soft));
+    }
+
+    #[test]
+    fn unknown_purge_mode_is_refused() {
+        assert_eq!(purge_mode(&purge_request(Some("gentle"))), None);
+        assert_eq!(purge_mode(&purge_request(Some(""))), None);
+    }
+
+    #[test]
+    fn only_the_purge_method_is_a_purge() {
+        assert!(is_purge_request(&purge_request(None)));
+        assert!(!is_purge_request(&RequestHeader::build(Method::DELETE, b"/page", None).unwrap()));
+    }
+}
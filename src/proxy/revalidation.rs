@@ -0,0 +1,47 @@
+use pingora::http::ResponseHeader;
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// Header fields describing the stored body or the connection rather than the resource itself.
+// These must never be replaced by the values carried in a 304.
+const PRESERVED_ON_REVALIDATION: [&str; 12] = [
+    "connection",
+    "content-encoding",
+    "content-length",
+    "content-range",
+    "keep-alive",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+    "vary",
+    "x-cdn-cache",
+];
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+/// Freshen a stored response header using the header of a `304 Not Modified` (RFC 9111 §4.3.4)
+///
+/// Pingora only merges a handful of caching headers into the stored response, leaving the `Date` and `Age` of the
+/// original response in place.
+/// That would make a successfully revalidated entry look as old as the original response and cause it to expire again
+/// immediately, so every end-to-end field in the 304 replaces its stored counterpart.
+pub fn refresh_stored_header(stored: &ResponseHeader, not_modified: &ResponseHeader) -> ResponseHeader {
+    let mut refreshed = stored.clone();
+
+    // The origin's view of the age of a revalidated response starts again from the 304
+    refreshed.remove_header("age");
+
+    for name in not_modified.headers.keys() {
+        if PRESERVED_ON_REVALIDATION.contains(&name.as_str()) {
+            continue;
+        }
+
+        refreshed.remove_header(name);
+
+        for value in not_modified.headers.get_all(name) {
+            refreshed.append_header(name.clone(), value).ok();
+        }
+    }
+
+    refreshed
+}
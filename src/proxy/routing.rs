@@ -0,0 +1,256 @@
+use crate::{
+    consts::{DEFAULT_PORT_HTTP, DEFAULT_PORT_HTTPS},
+    proxy::{
+        origin_pool::{HealthCheckCfg, OriginPool, PassiveCfg, Selection, UpstreamSpec},
+        peer_options::{PeerCfg, RetryCfg},
+    },
+    utils::parse_host_authority,
+};
+
+use pingora::http::StatusCode;
+use regex::Regex;
+use serde::{de::Error as _, Deserialize, Deserializer};
+use std::{net::SocketAddr, sync::OnceLock};
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+/// Map the host names served by this node to their origins
+///
+/// ```json
+/// "routing": {
+///   "unknown_host": "misdirected",
+///   "routes": [
+///     { "hosts": ["www.example.com", "*.example.com"], "upstreams": ["10.0.0.5:8443", "10.0.0.6:8443"],
+///       "tls": true, "sni": "origin.internal", "host_rewrite": "origin.internal",
+///       "health_check": { "path": "/healthz" }, "passive": { "max_failures": 3 },
+///       "peer": { "connect_timeout_ms": 1000, "read_timeout_ms": 30000, "http2": true },
+///       "retry": { "max_retries": 2 } },
+///     { "host_regex": "img[0-9]+\\.example\\.net", "upstreams": ["images.internal"], "tls": false, "port": 8080 }
+///   ]
+/// }
+/// ```
+///
+/// Host names in `hosts` must be given in lowercase; a regex is matched against the lowercased host name.
+/// An exact host name always wins, then the most specific `*.domain` wildcard, then the first route whose regex
+/// matches the whole host name.
+///
+/// Requests for hosts matching no route are answered with `421 Misdirected Request` (`misdirected`, the default) or
+/// `403 Forbidden` (`forbidden`).
+/// `passthrough` restores the old behaviour of connecting to whatever host the client names, which turns this node
+/// into an open proxy and should only be used for testing.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RoutingConfig {
+    pub unknown_host: UnknownHost,
+    pub routes: Vec<Route>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UnknownHost {
+    #[default]
+    Misdirected,
+    Forbidden,
+    Passthrough,
+}
+
+impl UnknownHost {
+    /// The status with which a request for an unknown host is rejected, or `None` if it is forwarded anyway
+    pub fn reject_status(&self) -> Option<StatusCode> {
+        match self {
+            UnknownHost::Misdirected => Some(StatusCode::MISDIRECTED_REQUEST),
+            UnknownHost::Forbidden => Some(StatusCode::FORBIDDEN),
+            UnknownHost::Passthrough => None,
+        }
+    }
+}
+
+impl RoutingConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        for (idx, route) in self.routes.iter().enumerate() {
+            if route.hosts.is_empty() && route.host_regex.is_none() {
+                return Err(format!("route {idx} has neither hosts nor host_regex"));
+            }
+
+            if let Some(host) = route.hosts.iter().find(|host| host.chars().any(|c| c.is_ascii_uppercase())) {
+                return Err(format!("route {idx} host \"{host}\" must be lowercase"));
+            }
+
+            if route.upstreams.is_empty() {
+                return Err(format!("route {idx} has no upstreams"));
+            }
+
+            if let Some(upstream) = route.upstreams.iter().find(|upstream| parse_host_authority(upstream.addr()).is_err()) {
+                return Err(format!("route {idx} has an invalid upstream \"{}\"", upstream.addr()));
+            }
+
+            // The health check must know which port and scheme to probe
+            if let Some(health_check) = &route.health_check {
+                if route.tls.is_none() {
+                    return Err(format!("route {idx} has a health check, so must say whether to use tls"));
+                }
+                health_check.validate().map_err(|e| format!("route {idx} {e}"))?;
+            }
+
+            route.passive.validate().map_err(|e| format!("route {idx} {e}"))?;
+            route.peer.validate().map_err(|e| format!("route {idx} {e}"))?;
+        }
+
+        Ok(())
+    }
+
+    // - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+    pub fn route_for(&self, host_lc: &str) -> Option<&Route> {
+        let with_host = |pattern: &str| self.routes.iter().find(|route| route.hosts.iter().any(|h| h == pattern));
+
+        if let Some(route) = with_host(host_lc) {
+            return Some(route);
+        }
+
+        let mut domain = host_lc;
+        while let Some((_, parent)) = domain.split_once('.') {
+            if let Some(route) = with_host(&format!("*.{parent}")) {
+                return Some(route);
+            }
+            domain = parent;
+        }
+
+        self.routes
+            .iter()
+            .find(|route| route.host_regex.as_ref().is_some_and(|re| re.is_match(host_lc)))
+    }
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+/// One entry in the routing table
+///
+/// Each upstream is a `host[:port]` address, or `{ "addr": "host[:port]", "weight": 3 }`, and requests are spread
+/// across them by `selection`.
+/// An upstream without a port uses the route's `port`, or else the default port for `tls`.
+/// If `tls` is not given, the origin is contacted using the same scheme as the client used.
+/// The SNI defaults to `host_rewrite`, or else the host name requested by the client.
+/// Timeouts and connection reuse are set by `peer`, and requests that fail to reach an origin are retried according
+/// to `retry`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Route {
+    #[serde(default)]
+    pub hosts: Vec<String>,
+    #[serde(default, deserialize_with = "deserialize_host_regex")]
+    pub host_regex: Option<Regex>,
+    pub upstreams: Vec<UpstreamSpec>,
+    #[serde(default)]
+    pub selection: Selection,
+    #[serde(default)]
+    pub health_check: Option<HealthCheckCfg>,
+    #[serde(default)]
+    pub passive: PassiveCfg,
+    #[serde(default)]
+    pub peer: PeerCfg,
+    #[serde(default)]
+    pub retry: RetryCfg,
+    #[serde(default)]
+    pub tls: Option<bool>,
+    #[serde(default)]
+    pub sni: Option<String>,
+    #[serde(default)]
+    pub host_rewrite: Option<String>,
+    #[serde(default)]
+    pub port: Option<u16>,
+    #[serde(skip)]
+    pool: OnceLock<OriginPool>,
+}
+
+/// The origin address chosen for a request
+#[derive(Debug)]
+pub struct Upstream {
+    /// The backend as it is known to the origin pool, which is how its health is reported
+    pub backend: SocketAddr,
+    /// The address to connect to
+    pub addr: SocketAddr,
+    pub tls: bool,
+    pub sni: String,
+}
+
+impl Route {
+    pub fn set_pool(&self, pool: OriginPool) -> Result<(), OriginPool> {
+        self.pool.set(pool)
+    }
+
+    /// The route's backends, which only exist once the origin pools have been started
+    pub fn pool(&self) -> Option<&OriginPool> {
+        self.pool.get()
+    }
+
+    /// Choose a backend for a request, or `None` if no backend is available.
+    /// The `key` is only used by consistent hashing
+    pub fn select_upstream(&self, client_tls: bool, requested_host: &str, key: &[u8]) -> Option<Upstream> {
+        let backend = self.pool()?.select(key)?;
+        let mut addr = backend;
+        let tls = self.tls.unwrap_or(client_tls);
+
+        // The port of an upstream that gave none depends on the scheme of this request
+        if addr.port() == 0 {
+            addr.set_port(if tls { DEFAULT_PORT_HTTPS } else { DEFAULT_PORT_HTTP });
+        }
+
+        let sni = if tls {
+            self.sni
+                .clone()
+                .or_else(|| self.host_rewrite.clone())
+                .unwrap_or_else(|| requested_host.to_string())
+        } else {
+            String::new()
+        };
+
+        Some(Upstream { backend, addr, tls, sni })
+    }
+
+    /// Feed the outcome of a request to the backend's passive health check
+    pub fn report(&self, backend: SocketAddr, success: bool) {
+        if let Some(pool) = self.pool() {
+            if success {
+                pool.report_success(backend)
+            } else {
+                pool.report_failure(backend)
+            }
+        }
+    }
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// The pattern must match the whole host name, so it is anchored at both ends
+fn deserialize_host_regex<'de, D>(deserializer: D) -> Result<Option<Regex>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|pattern| Regex::new(&format!("^(?:{pattern})$")).map_err(D::Error::custom))
+        .transpose()
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validate(route: &str) -> Result<(), String> {
+        let routing: RoutingConfig = serde_json::from_str(&format!(r#"{{ "routes": [{route}] }}"#)).unwrap();
+        routing.validate()
+    }
+
+    #[test]
+    fn passive_max_failures_must_be_at_least_one() {
+        let route = |max_failures| {
+            let passive = format!(r#"{{ "max_failures": {max_failures} }}"#);
+            format!(r#"{{ "hosts": ["a.example.com"], "upstreams": ["10.0.0.1"], "passive": {passive} }}"#)
+        };
+
+        assert_eq!(validate(&route(0)), Err("route 0 passive max_failures must be at least 1".to_string()));
+        assert_eq!(validate(&route(1)), Ok(()));
+    }
+
+    #[test]
+    fn default_passive_check_is_valid() {
+        assert_eq!(validate(r#"{ "hosts": ["a.example.com"], "upstreams": ["10.0.0.1"] }"#), Ok(()));
+    }
+}
@@ -0,0 +1,292 @@
+use crate::proxy::forwarding::node_id;
+
+use pingora::http::{HMap, ResponseHeader};
+use pingora_cache::cache_control::CacheControl;
+
+const CDN_CACHE_CONTROL: &str = "cdn-cache-control";
+const SURROGATE_CONTROL: &str = "surrogate-control";
+
+/// Response headers that instruct this cache rather than the client.
+/// Neither is ever sent to clients
+pub const EDGE_CONTROL_HEADERS: [&str; 2] = [CDN_CACHE_CONTROL, SURROGATE_CONTROL];
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+/// The cache directives this cache obeys for a response
+///
+/// These are taken from the first of the following that is present:
+///
+///   1. `CDN-Cache-Control` (RFC 9213), which has the same syntax as `Cache-Control`
+///   2. `Surrogate-Control` (W3C Edge Architecture 1.0), if it carries `max-age` or `no-store` for this node
+///   3. `Cache-Control`
+///
+/// As RFC 9213 §2.2 requires, a targeted field replaces `Cache-Control` entirely rather than being merged with it.
+/// This allows an origin to send `Cache-Control: no-cache` to browsers while letting the edge hold the object for
+/// hours
+pub fn edge_cache_control(resp: &ResponseHeader) -> Option<CacheControl> {
+    // RFC 9213 §2.1: a targeted field that cannot be parsed is ignored
+    let cdn_cache_control =
+        CacheControl::from_resp_headers_named(CDN_CACHE_CONTROL, resp).filter(|cc| !cc.directives.is_empty());
+
+    cdn_cache_control
+        .or_else(|| SurrogateControl::from_response(resp).and_then(|sc| sc.to_cache_control()))
+        .or_else(|| CacheControl::from_resp_headers(resp))
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+/// The `Surrogate-Control` directives that apply to this node
+///
+/// ```text
+/// Surrogate-Control: max-age=300+60, content="ESI/1.0";edge-lon-1, no-store;edge-nyc-2
+/// ```
+///
+/// A directive followed by `;<device-token>` only applies to the node whose `EDGE_NODE_ID` matches the token.
+/// If any directive is targeted at this node, the untargeted directives are ignored.
+/// The optional `+<seconds>` after `max-age` is how long the object may be served stale if the origin cannot be
+/// reached, so becomes `stale-if-error`
+#[derive(Debug, Default)]
+pub struct SurrogateControl {
+    pub max_age: Option<u32>,
+    pub stale: Option<u32>,
+    pub no_store: bool,
+    /// The processing capabilities (such as `ESI/1.0`) the origin asks this node to apply to the body
+    pub content: Vec<String>,
+}
+
+impl SurrogateControl {
+    pub fn from_response(resp: &ResponseHeader) -> Option<Self> {
+        let mut untargeted = Self::default();
+        let mut targeted = Self::default();
+        let mut is_targeted = false;
+        let mut present = false;
+
+        for value in resp.headers.get_all(SURROGATE_CONTROL).iter().filter_map(|v| v.to_str().ok()) {
+            present = true;
+
+            for item in split_unquoted(value, ',') {
+                let mut parts = split_unquoted(item, ';').into_iter();
+                let directive = parts.next().unwrap_or_default();
+
+                match parts.next() {
+                    Some(target) if target.eq_ignore_ascii_case(node_id()) => {
+                        is_targeted = true;
+                        targeted.apply(directive);
+                    },
+                    Some(_) => {},
+                    None => untargeted.apply(directive),
+                }
+            }
+        }
+
+        present.then_some(if is_targeted { targeted } else { untargeted })
+    }
+
+    fn apply(&mut self, directive: &str) {
+        let (name, value) = match directive.split_once('=') {
+            Some((name, value)) => (name.trim(), Some(value.trim())),
+            None => (directive, None),
+        };
+
+        match (name.to_ascii_lowercase().as_str(), value) {
+            // This node is always remote from the origin, so it honours no-store-remote as well
+            ("no-store" | "no-store-remote", _) => self.no_store = true,
+            ("max-age", Some(value)) => {
+                let (max_age, stale) = match value.split_once('+') {
+                    Some((max_age, stale)) => (max_age, Some(stale)),
+                    None => (value, None),
+                };
+
+                if let Ok(max_age) = max_age.trim().parse() {
+                    self.max_age = Some(max_age);
+                    self.stale = stale.and_then(|s| s.trim().parse().ok());
+                } else {
+                    tracing::debug!("     ignoring malformed Surrogate-Control max-age \"{value}\"");
+                }
+            },
+            ("content", Some(value)) => {
+                self.content = value
+                    .trim_matches('"')
+                    .split_ascii_whitespace()
+                    .map(String::from)
+                    .collect();
+            },
+            _ => {},
+        }
+    }
+
+    /// The equivalent `Cache-Control` directives, or `None` if nothing here affects how long the object is stored
+    pub fn to_cache_control(&self) -> Option<CacheControl> {
+        // This node does not process the body, so it is stored and served exactly as the origin sent it
+        if !self.content.is_empty() {
+            tracing::debug!(
+                "     ignoring Surrogate-Control content=\"{}\": body processing is not supported",
+                self.content.join(" ")
+            );
+        }
+
+        let mut directives = Vec::new();
+        if self.no_store {
+            directives.push("no-store".to_string());
+        }
+        if let Some(max_age) = self.max_age {
+            directives.push(format!("max-age={max_age}"));
+        }
+        if let Some(stale) = self.stale {
+            directives.push(format!("stale-if-error={stale}"));
+        }
+
+        if directives.is_empty() {
+            return None;
+        }
+
+        let mut headers = HMap::new();
+        headers.insert("cache-control", directives.join(", ").parse().ok()?);
+        CacheControl::from_headers_named("cache-control", &headers)
+    }
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// Split on a delimiter that is not inside a quoted string, trimming each part and dropping empty ones
+fn split_unquoted(value: &str, delimiter: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut in_quotes = false;
+    let mut escaped = false;
+    let mut start = 0;
+
+    for (i, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_quotes => escaped = true,
+            '"' => in_quotes = !in_quotes,
+            c if c == delimiter && !in_quotes => {
+                parts.push(&value[start..i]);
+                start = i + c.len_utf8();
+            },
+            _ => {},
+        }
+    }
+    parts.push(&value[start..]);
+
+    parts.into_iter().map(str::trim).filter(|part| !part.is_empty()).collect()
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(headers: &[(&'static str, &str)]) -> ResponseHeader {
+        let mut resp = ResponseHeader::build(200, None).unwrap();
+        for (name, value) in headers {
+            resp.append_header(*name, *value).unwrap();
+        }
+        resp
+    }
+
+    fn applied(directives: &[&str]) -> SurrogateControl {
+        let mut sc = SurrogateControl::default();
+        for directive in directives {
+            sc.apply(directive);
+        }
+        sc
+    }
+
+    #[test]
+    fn split_unquoted_trims_and_drops_empty_parts() {
+        assert_eq!(split_unquoted(" max-age=60 ,, no-store ,", ','), ["max-age=60", "no-store"]);
+        assert!(split_unquoted("", ',').is_empty());
+    }
+
+    #[test]
+    fn split_unquoted_ignores_delimiters_inside_quotes() {
+        assert_eq!(
+            split_unquoted(r#"content="ESI/1.0, ESI-INV/1.0";edge-1, max-age=10"#, ','),
+            [r#"content="ESI/1.0, ESI-INV/1.0";edge-1"#, "max-age=10"]
+        );
+        assert_eq!(split_unquoted(r#"content="a;b";edge-1"#, ';'), [r#"content="a;b""#, "edge-1"]);
+    }
+
+    #[test]
+    fn split_unquoted_honours_escaped_quotes() {
+        assert_eq!(split_unquoted(r#"a="x\",y", b"#, ','), [r#"a="x\",y""#, "b"]);
+        // An unterminated quote swallows the rest of the value
+        assert_eq!(split_unquoted(r#"a="x, b"#, ','), [r#"a="x, b"#]);
+    }
+
+    #[test]
+    fn apply_max_age_with_stale_extension() {
+        let sc = applied(&["max-age=300+60"]);
+        assert_eq!((sc.max_age, sc.stale), (Some(300), Some(60)));
+
+        let sc = applied(&["MAX-AGE = 300"]);
+        assert_eq!((sc.max_age, sc.stale), (Some(300), None));
+    }
+
+    #[test]
+    fn apply_ignores_malformed_max_age() {
+        let sc = applied(&["max-age=300+60", "max-age=soon"]);
+        assert_eq!((sc.max_age, sc.stale), (Some(300), Some(60)));
+
+        let sc = applied(&["max-age=300+later"]);
+        assert_eq!((sc.max_age, sc.stale), (Some(300), None));
+
+        assert_eq!(applied(&["max-age"]).max_age, None);
+    }
+
+    #[test]
+    fn apply_no_store_and_content() {
+        assert!(applied(&["no-store"]).no_store);
+        assert!(applied(&["no-store-remote"]).no_store);
+        assert_eq!(applied(&[r#"content="ESI/1.0 ESI-INV/1.0""#]).content, ["ESI/1.0", "ESI-INV/1.0"]);
+        assert!(!applied(&["private"]).no_store);
+    }
+
+    #[test]
+    fn targeted_directives_replace_untargeted_ones() {
+        let value = format!("max-age=10, max-age=600+30;{}, no-store;some-other-node", node_id());
+        let sc = SurrogateControl::from_response(&response(&[(SURROGATE_CONTROL, &value)])).unwrap();
+
+        assert_eq!((sc.max_age, sc.stale, sc.no_store), (Some(600), Some(30), false));
+    }
+
+    #[test]
+    fn directives_for_other_nodes_are_ignored() {
+        let value = "max-age=10, no-store;some-other-node";
+        let sc = SurrogateControl::from_response(&response(&[(SURROGATE_CONTROL, value)])).unwrap();
+
+        assert_eq!((sc.max_age, sc.no_store), (Some(10), false));
+        assert!(SurrogateControl::from_response(&response(&[])).is_none());
+    }
+
+    #[test]
+    fn cdn_cache_control_takes_precedence() {
+        let resp = response(&[
+            ("cache-control", "no-cache"),
+            (SURROGATE_CONTROL, "max-age=60"),
+            (CDN_CACHE_CONTROL, "max-age=3600"),
+        ]);
+        assert_eq!(edge_cache_control(&resp).unwrap().max_age().unwrap(), Some(3600));
+    }
+
+    #[test]
+    fn surrogate_control_replaces_cache_control() {
+        let resp = response(&[("cache-control", "max-age=5, private"), (SURROGATE_CONTROL, "max-age=60+30")]);
+        let cc = edge_cache_control(&resp).unwrap();
+
+        assert_eq!(cc.max_age().unwrap(), Some(60));
+        assert!(cc.has_key("stale-if-error"));
+        assert!(!cc.has_key("private"));
+    }
+
+    #[test]
+    fn cache_control_is_used_when_no_edge_directives_apply() {
+        // An empty CDN-Cache-Control and a Surrogate-Control carrying nothing about storage are both ignored
+        let resp = response(&[
+            ("cache-control", "max-age=5"),
+            (CDN_CACHE_CONTROL, ""),
+            (SURROGATE_CONTROL, r#"content="ESI/1.0""#),
+        ]);
+        assert_eq!(edge_cache_control(&resp).unwrap().max_age().unwrap(), Some(5));
+
+        assert!(edge_cache_control(&response(&[])).is_none());
+    }
+}
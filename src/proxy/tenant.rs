@@ -0,0 +1,69 @@
+use crate::{
+    config::{edge_config, lookup_host},
+    disk_cache::tenants::valid_tenant_name,
+    proxy::trusted_proxies::from_trusted_proxy,
+};
+
+use pingora::prelude::Session;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+/// How requests are assigned to tenants, each of which has its own cache namespace
+///
+/// ```json
+/// "tenants": {
+///   "header": "x-edge-tenant",
+///   "hosts": { "www.acme.com": "acme", "*.acme-static.net": "acme", "globex.example": "globex" }
+/// }
+/// ```
+///
+/// The header is only honoured on connections from a trusted proxy; otherwise the tenant is found by host name using
+/// the same exact and `*.domain` matching as the cache key rules.
+/// Requests matching neither belong to the default tenant.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TenantConfig {
+    pub header: Option<String>,
+    pub hosts: HashMap<String, String>,
+}
+
+impl TenantConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        match self.hosts.iter().find(|(_, tenant)| !valid_tenant_name(tenant)) {
+            Some((host, tenant)) => Err(format!(
+                "tenant \"{tenant}\" for host \"{host}\" may only contain ASCII letters, digits, '-' and '_'"
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+/// Resolve the tenant of a request, returning an empty string for the default tenant
+///
+/// Pingora's rustls listener does not expose the SNI of a connection, so when clients connect directly, the tenant is
+/// resolved from the `Host` header (which TLS clients normally set to the same name)
+pub fn resolve_tenant(session: &Session, host_lc: &str) -> String {
+    let cfg = &edge_config().tenants;
+
+    let from_header = cfg
+        .header
+        .as_deref()
+        .filter(|_| from_trusted_proxy(session))
+        .and_then(|name| session.req_header().headers.get(name))
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+        .filter(|tenant| {
+            let valid = valid_tenant_name(tenant);
+            if !valid {
+                tracing::warn!("Ignoring invalid tenant name \"{tenant}\" in request header");
+            }
+            valid
+        });
+
+    from_header
+        .or_else(|| lookup_host(&cfg.hosts, host_lc).map(String::as_str))
+        .unwrap_or_default()
+        .to_string()
+}
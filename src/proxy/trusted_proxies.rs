@@ -0,0 +1,60 @@
+use crate::{consts::DEFAULT_TRUSTED_PROXY_CIDRS, utils::env_var_or_str};
+
+use ipnet::IpNet;
+use pingora::prelude::Session;
+use std::{net::IpAddr, sync::OnceLock};
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// Headers such as X-Forwarded-Proto are only believed when the connection comes from one of these networks.
+// By default, no address is trusted
+pub struct TrustedProxiesCfg {
+    pub cidrs: Vec<IpNet>,
+}
+
+static TRUSTED_PROXIES_CFG: OnceLock<TrustedProxiesCfg> = OnceLock::new();
+pub fn trusted_proxies_cfg() -> &'static TrustedProxiesCfg {
+    TRUSTED_PROXIES_CFG.get_or_init(|| TrustedProxiesCfg {
+        cidrs: parse_cidrs(&env_var_or_str("TRUSTED_PROXY_CIDRS", DEFAULT_TRUSTED_PROXY_CIDRS)),
+    })
+}
+
+impl TrustedProxiesCfg {
+    pub fn is_trusted(&self, addr: IpAddr) -> bool {
+        // An IPv4 client accepted on a dual-stack socket appears as ::ffff:a.b.c.d
+        let addr = addr.to_canonical();
+        self.cidrs.iter().any(|net| net.contains(&addr))
+    }
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+/// Parse a comma-separated list of networks such as `10.0.0.0/8, 192.168.1.7, fd00::/8`
+///
+/// A bare address is treated as a single-host network.
+/// Malformed entries are logged and skipped
+pub fn parse_cidrs(spec: &str) -> Vec<IpNet> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .filter_map(|entry| {
+            let net = entry
+                .parse::<IpNet>()
+                .or_else(|_| entry.parse::<IpAddr>().map(IpNet::from))
+                .ok();
+
+            if net.is_none() {
+                tracing::warn!("Ignoring malformed network \"{entry}\"");
+            }
+
+            net
+        })
+        .collect()
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+/// Is the peer on the other end of this connection a trusted proxy?
+pub fn from_trusted_proxy(session: &Session) -> bool {
+    session
+        .client_addr()
+        .and_then(|addr| addr.as_inet())
+        .is_some_and(|inet| trusted_proxies_cfg().is_trusted(inet.ip()))
+}
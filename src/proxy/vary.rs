@@ -0,0 +1,116 @@
+use pingora::http::{RequestHeader, ResponseHeader};
+use pingora_cache::{key::HashBinary, VarianceBuilder};
+
+const ACCEPT_ENCODING: &str = "accept-encoding";
+const BROTLI: &str = "br";
+const GZIP: &str = "gzip";
+const IDENTITY: &str = "identity";
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+/// Reduce an `Accept-Encoding` value to the single encoding we will ask the origin for
+///
+/// Clients spell the same preference in many different ways (`gzip, deflate, br`, `br;q=1.0, gzip;q=0.8`, ...), so
+/// using the raw value in the variance key would fragment the cache. Instead, every request falls into one of three
+/// buckets: `br`, `gzip` or `identity`. Codings given a q-value of zero are treated as not acceptable.
+pub fn normalize_accept_encoding(value: &str) -> &'static str {
+    let mut br = false;
+    let mut gzip = false;
+
+    for item in value.split(',') {
+        let mut parts = item.split(';');
+        let coding = parts.next().unwrap_or_default().trim();
+        let rejected = parts.any(|p| {
+            p.trim()
+                .strip_prefix("q=")
+                .and_then(|q| q.trim().parse::<f32>().ok())
+                .is_some_and(|q| q <= 0.0)
+        });
+
+        if rejected {
+            continue;
+        }
+
+        if coding.eq_ignore_ascii_case(BROTLI) {
+            br = true;
+        } else if coding.eq_ignore_ascii_case(GZIP) || coding.eq_ignore_ascii_case("x-gzip") || coding == "*" {
+            gzip = true;
+        }
+    }
+
+    if br {
+        BROTLI
+    } else if gzip {
+        GZIP
+    } else {
+        IDENTITY
+    }
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// Rewrite the request's Accept-Encoding header to its normalised form.
+// The origin is then asked for exactly the encoding named in the variance key, so every client sharing that variant
+// can accept the stored body.
+pub fn normalize_request(req: &mut RequestHeader) {
+    let normalized = normalize_accept_encoding(&joined_header_values(req, ACCEPT_ENCODING));
+    req.insert_header(ACCEPT_ENCODING, normalized).ok();
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// The lowercase, deduplicated names of the request headers listed in a response's Vary header(s)
+pub fn vary_header_names(resp: &ResponseHeader) -> Vec<String> {
+    let mut names: Vec<String> = resp
+        .headers
+        .get_all("vary")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|name| name.trim().to_ascii_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect();
+
+    names.sort_unstable();
+    names.dedup();
+    names
+}
+
+// RFC 9110 §12.5.5: "Vary: *" means the response varies on something other than the request headers
+pub fn varies_on_everything(resp: &ResponseHeader) -> bool {
+    vary_header_names(resp).iter().any(|name| name == "*")
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+/// Compute the variance key of a request against the Vary header of a cached response
+///
+/// Returns `None` when the response does not vary, in which case the asset lives only in the primary slot.
+/// A header absent from the request contributes an empty value, so it still selects a variant of its own.
+pub fn variance_key(resp: &ResponseHeader, req: &RequestHeader) -> Option<HashBinary> {
+    let names = vary_header_names(resp);
+    let mut variance = VarianceBuilder::new();
+
+    for name in names.iter() {
+        let value = joined_header_values(req, name);
+        let value = if name == ACCEPT_ENCODING {
+            normalize_accept_encoding(&value).to_string()
+        } else {
+            value
+        };
+
+        variance.add_owned_value(name, value.into_bytes());
+    }
+
+    variance.finalize()
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// Combine multiple instances of the same header into one comma separated value with insignificant whitespace removed
+fn joined_header_values(req: &RequestHeader, name: &str) -> String {
+    req.headers
+        .get_all(name)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .collect::<Vec<_>>()
+        .join(",")
+}
@@ -2,7 +2,7 @@
 // mod fan_out;
 
 use crate::{
-    disk_cache::disk_cache,
+    disk_cache::{disk_cache, soft_purge::PurgeMode, tags::TagPurge, DiskCache},
     logger::{impl_trace, Trace},
 };
 
@@ -10,7 +10,7 @@ use async_trait::async_trait;
 // use fan_out::*;
 use pingora_cache::{
     key::CompactCacheKey, storage::{HitHandler, MissHandler, PurgeType, Storage},
-    trace::SpanHandle,
+    trace::{Span, SpanHandle},
     CacheKey,
     CacheMeta,
 };
@@ -40,6 +40,25 @@ pub enum WritePolicy {
     WriteThroughBoth,
 }
 
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+/// The tier that satisfied a lookup.
+///
+/// This is recorded in the extensions of the `CacheMeta` returned by `lookup()` so that the proxy can report it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CacheTier {
+    Primary,
+    Secondary,
+}
+
+impl CacheTier {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CacheTier::Primary => "primary",
+            CacheTier::Secondary => "secondary",
+        }
+    }
+}
+
 // - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
 /// Tiered storage.
 ///
@@ -69,6 +88,86 @@ impl TieredStorage {
             write_policy,
         }
     }
+
+    // - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+    /// Invalidate every object carrying a `Surrogate-Key` or `Cache-Tag`, in all tiers
+    ///
+    /// The tag index only lives next to the primary `DiskCache`, so it supplies the keys for both tiers
+    pub async fn purge_tag(&'static self, tag: &str, mode: PurgeMode) -> TagPurge {
+        let fn_name = "purge_tag";
+        <Self as Trace>::fn_enter(fn_name);
+
+        let keys = disk_cache().tags.keys_for(tag);
+        let span = Span::inactive().handle();
+        let mut purged = 0;
+
+        for key in &keys {
+            match mode {
+                // The primary removes the object and all of its variants from the eviction manager
+                PurgeMode::Hard => {
+                    if self.purge(key, PurgeType::Invalidation, &span).await.unwrap_or_default() {
+                        purged += 1;
+                    }
+                    disk_cache().tags.forget(key);
+                },
+                // An expired object keeps its tags, since it is still stored
+                PurgeMode::Soft => {
+                    if self.soft_purge(key).await {
+                        purged += 1;
+                    }
+                },
+            }
+        }
+
+        tracing::info!("Purged ({mode:?}) {purged} of {} objects tagged {tag}", keys.len());
+        <Self as Trace>::fn_exit(fn_name);
+        TagPurge {
+            tag: tag.to_string(),
+            mode,
+            keys: keys.len(),
+            purged,
+        }
+    }
+
+    // - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+    /// Mark an object as expired in the primary tier, keeping its body so that it can still be served stale.
+    ///
+    /// The `Storage` trait has no way to expire an object, so only a `DiskCache` tier can be soft purged.
+    /// Any other tier is invalidated instead, otherwise an object later evicted from the primary could be served fresh
+    /// from the secondary
+    pub async fn soft_purge(&'static self, key: &CompactCacheKey) -> bool {
+        let fn_name = "soft_purge";
+        <Self as Trace>::fn_enter(fn_name);
+
+        let span = Span::inactive().handle();
+        let mut expired = match self.primary.as_any().downcast_ref::<DiskCache>() {
+            Some(primary) => primary.expire(key).await,
+            None => match self.primary.purge(key, PurgeType::Invalidation, &span).await {
+                Ok(x) => x,
+                Err(e) => {
+                    tracing::warn!("primary purge failed during soft purge: {e}");
+                    false
+                },
+            },
+        };
+
+        if let Some(sec) = self.secondary {
+            match sec.purge(key, PurgeType::Invalidation, &span).await {
+                Ok(x) => expired |= x,
+                Err(e) => tracing::warn!("secondary purge failed during soft purge: {e}"),
+            }
+        }
+
+        <Self as Trace>::fn_exit(fn_name);
+        expired
+    }
+
+    // - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+    /// Invalidate an object and all of its variants in every tier, as a hard PURGE does
+    pub async fn invalidate(&'static self, key: &CompactCacheKey) -> bool {
+        let span = Span::inactive().handle();
+        self.purge(key, PurgeType::Invalidation, &span).await.unwrap_or_default()
+    }
 }
 
 // - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
@@ -84,14 +183,10 @@ impl Storage for TieredStorage {
 
         let response: Option<(CacheMeta, HitHandler)> = if let Some(hit) = self.primary.lookup(key, trace).await? {
             // Response from primary
-            Some(hit)
+            Some(tag_tier(hit, CacheTier::Primary))
         } else if let Some(secondary) = self.secondary {
-            if let Some(hit) = secondary.lookup(key, trace).await? {
-                // Response from secondary
-                Some(hit)
-            } else {
-                None
-            }
+            // Response from secondary (if any)
+            secondary.lookup(key, trace).await?.map(|hit| tag_tier(hit, CacheTier::Secondary))
         } else {
             None
         };
@@ -180,10 +275,10 @@ impl Storage for TieredStorage {
         // Update primary; best-effort mirror to secondary if present.
         let mut updated = self.primary.update_meta(key, meta, trace).await?;
 
-        if let Some(sec) = self.secondary {
-            if let Ok(x) = sec.update_meta(key, meta, trace).await {
-                updated |= x;
-            }
+        if let Some(sec) = self.secondary
+            && let Ok(x) = sec.update_meta(key, meta, trace).await
+        {
+            updated |= x;
         }
 
         <Self as Trace>::fn_exit(fn_name);
@@ -195,3 +290,9 @@ impl Storage for TieredStorage {
         self
     }
 }
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+fn tag_tier((mut meta, hit): (CacheMeta, HitHandler), tier: CacheTier) -> (CacheMeta, HitHandler) {
+    meta.extensions_mut().insert(tier);
+    (meta, hit)
+}